@@ -0,0 +1,301 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::string::String;
+use core::mem;
+use plain::Plain;
+
+/// Module type of chipset ACMs
+pub const MODULE_TYPE_CHIPSET: u16 = 2;
+
+/// UUID of the ACM information table, 7FC03AAA-46A7-18DB-AC2E-698F8D417F5A
+pub const INFO_TABLE_UUID: [u8; 16] = [
+    0xAA, 0x3A, 0xC0, 0x7F, 0xA7, 0x46, 0xDB, 0x18,
+    0x2E, 0xAC, 0x69, 0x8F, 0x8D, 0x41, 0x7F, 0x5A,
+];
+
+/// Authenticated Code Module header
+#[repr(packed)]
+pub struct Header {
+    pub module_type: u16,
+    pub module_subtype: u16,
+    /// Header length in dwords, including the key and signature but not the scratch space
+    pub header_length: u32,
+    pub header_version: u32,
+    pub chipset_id: u16,
+    pub flags: u16,
+    pub vendor: u32,
+    /// Build date in BCD, as 0xYYYYMMDD
+    pub date: u32,
+    /// Module size in dwords
+    pub size: u32,
+    pub txt_svn: u16,
+    pub se_svn: u16,
+    pub code_control: u32,
+    pub error_entry_point: u32,
+    pub gdt_limit: u32,
+    pub gdt_base: u32,
+    pub segment_selector: u32,
+    pub entry_point: u32,
+    pub reserved: [u8; 64],
+    /// Public key size in dwords
+    pub key_size: u32,
+    /// Scratch size in dwords, the scratch space follows the header
+    pub scratch_size: u32,
+}
+
+impl Header {
+    pub fn valid(&self) -> bool {
+        self.module_type == MODULE_TYPE_CHIPSET && self.vendor == 0x8086
+    }
+
+    /// True if signed with a debug key
+    pub fn debug(&self) -> bool {
+        self.flags & (1 << 15) != 0
+    }
+
+    /// True if the module is a pre-production release
+    pub fn pre_production(&self) -> bool {
+        self.flags & (1 << 14) != 0
+    }
+}
+
+unsafe impl Plain for Header {}
+
+#[repr(packed)]
+pub struct InfoTable {
+    pub uuid: [u8; 16],
+    /// 0 for BIOS ACMs, 1 for SINIT ACMs
+    pub chipset_acm_type: u8,
+    pub version: u8,
+    pub length: u16,
+    /// Offset of the chipset ID list from the start of the module
+    pub chipset_id_list: u32,
+    pub os_sinit_table_version: u32,
+    pub min_mle_header_version: u32,
+    pub capabilities: u32,
+    pub acm_version: u8,
+    pub acm_revision: [u8; 3],
+    /// Offset of the processor ID list from the start of the module, from version 4
+    pub processor_id_list: u32,
+}
+
+unsafe impl Plain for InfoTable {}
+
+#[repr(packed)]
+pub struct ChipsetId {
+    /// Bit 0 set if `revision_id` is a mask
+    pub flags: u32,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub revision_id: u16,
+    pub reserved: [u16; 3],
+}
+
+impl ChipsetId {
+    /// True if this entry matches a chipset's PCI vendor, device and revision IDs
+    pub fn matches(&self, vendor_id: u16, device_id: u16, revision_id: u16) -> bool {
+        let revision_match = if self.flags & 1 != 0 {
+            self.revision_id & revision_id != 0
+        } else {
+            self.revision_id == revision_id
+        };
+        self.vendor_id == vendor_id && self.device_id == device_id && revision_match
+    }
+}
+
+unsafe impl Plain for ChipsetId {}
+
+#[repr(packed)]
+pub struct ProcessorId {
+    /// CPUID family, model and stepping
+    pub fms: u32,
+    pub fms_mask: u32,
+    /// Value of MSR 0x17, holding the platform ID in bits 52:50
+    pub platform_id: u64,
+    pub platform_mask: u64,
+}
+
+impl ProcessorId {
+    pub fn matches(&self, fms: u32, platform_id: u64) -> bool {
+        (self.fms & self.fms_mask) == (fms & self.fms_mask)
+            && (self.platform_id & self.platform_mask) == (platform_id & self.platform_mask)
+    }
+}
+
+unsafe impl Plain for ProcessorId {}
+
+/// Authenticated Code Module, such as the Startup ACM for Boot Guard and TXT
+pub struct Acm<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Acm<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Acm<'a>, String> {
+        let header = plain::from_bytes::<Header>(data).map_err(|err| {
+            format!("ACM header invalid: {:?}", err)
+        })?;
+        if ! header.valid() {
+            let (module_type, vendor) = (header.module_type, header.vendor);
+            return Err(format!("ACM header invalid: type {:#X}, vendor {:#X}", module_type, vendor));
+        }
+
+        let size = header.size as usize * 4;
+        if size > data.len() || (header.header_length as usize) * 4 > size {
+            return Err(format!("ACM truncated: {:#X} > {:#X}", size, data.len()));
+        }
+
+        Ok(Acm {
+            data: &data[..size],
+        })
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn header(&self) -> &'a Header {
+        plain::from_bytes(self.data).unwrap()
+    }
+
+    /// Build date as year, month and day
+    pub fn date(&self) -> (u16, u8, u8) {
+        let date = self.header().date;
+        let bcd = |value: u32| (value >> 4) * 10 + (value & 0xF);
+        let year = bcd(date >> 24) * 100 + bcd((date >> 16) & 0xFF);
+        (year as u16, bcd((date >> 8) & 0xFF) as u8, bcd(date & 0xFF) as u8)
+    }
+
+    /// RSA public key modulus, little endian
+    pub fn modulus(&self) -> &'a [u8] {
+        let start = mem::size_of::<Header>();
+        let len = self.header().key_size as usize * 4;
+        self.data.get(start..start + len).unwrap_or(&[])
+    }
+
+    /// RSA signature, little endian
+    pub fn signature(&self) -> &'a [u8] {
+        let header = self.header();
+        let mut start = mem::size_of::<Header>() + header.key_size as usize * 4;
+        // Version 0 headers store the public exponent before the signature
+        if header.header_version == 0 {
+            start += 4;
+        }
+        self.data.get(start..start + header.key_size as usize * 4).unwrap_or(&[])
+    }
+
+    /// Information table at the start of the module body, after the header and scratch space
+    pub fn info_table(&self) -> Result<&'a InfoTable, String> {
+        let header = self.header();
+        let offset = (header.header_length as usize + header.scratch_size as usize) * 4;
+        let table = plain::from_bytes::<InfoTable>(self.data.get(offset..).unwrap_or(&[])).map_err(|err| {
+            format!("ACM information table invalid: {:?}", err)
+        })?;
+        if table.uuid != INFO_TABLE_UUID {
+            return Err(String::from("ACM information table UUID invalid"));
+        }
+        Ok(table)
+    }
+
+    /// Read a list of `count: u32` followed by entries at an offset in the module
+    fn list<T: Plain>(&self, offset: usize, name: &str) -> Result<&'a [T], String> {
+        let count = self.data.get(offset..offset + 4).ok_or_else(|| {
+            format!("ACM {} list truncated", name)
+        })?;
+        let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+        plain::slice_from_bytes_len(&self.data[offset + 4..], count).map_err(|err| {
+            format!("ACM {} list invalid: {:?}", name, err)
+        })
+    }
+
+    pub fn chipset_ids(&self) -> Result<&'a [ChipsetId], String> {
+        let offset = self.info_table()?.chipset_id_list as usize;
+        self.list(offset, "chipset ID")
+    }
+
+    /// Processor IDs, empty for information tables before version 4
+    pub fn processor_ids(&self) -> Result<&'a [ProcessorId], String> {
+        let table = self.info_table()?;
+        if table.version < 4 {
+            return Ok(&[]);
+        }
+        let offset = table.processor_id_list as usize;
+        self.list(offset, "processor ID")
+    }
+
+    /// True if the ACM supports a chipset with these PCI vendor, device and revision IDs
+    pub fn matches_chipset(&self, vendor_id: u16, device_id: u16, revision_id: u16) -> Result<bool, String> {
+        Ok(self.chipset_ids()?.iter().any(|id| id.matches(vendor_id, device_id, revision_id)))
+    }
+
+    /// True if the ACM supports a processor, or does not restrict processors
+    pub fn matches_processor(&self, fms: u32, platform_id: u64) -> Result<bool, String> {
+        let ids = self.processor_ids()?;
+        Ok(ids.is_empty() || ids.iter().any(|id| id.matches(fms, platform_id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn write(data: &mut [u8], offset: usize, value: &[u8]) {
+        data[offset..offset + value.len()].copy_from_slice(value);
+    }
+
+    /// ACM with 0x40 bytes of scratch space, supporting chipset 8086:B002 revision 1 and
+    /// processors 906EX
+    fn acm() -> Vec<u8> {
+        let mut data = vec![0; 0x200];
+        write(&mut data, 0x00, &MODULE_TYPE_CHIPSET.to_le_bytes());
+        write(&mut data, 0x04, &0x20u32.to_le_bytes());
+        write(&mut data, 0x10, &0x8086u32.to_le_bytes());
+        write(&mut data, 0x14, &0x20190425u32.to_le_bytes());
+        write(&mut data, 0x18, &0x80u32.to_le_bytes());
+        write(&mut data, 0x7C, &0x10u32.to_le_bytes());
+
+        // Information table version 4
+        write(&mut data, 0xC0, &INFO_TABLE_UUID);
+        data[0xD1] = 4;
+        write(&mut data, 0xD4, &0x100u32.to_le_bytes());
+        write(&mut data, 0xE8, &0x120u32.to_le_bytes());
+
+        write(&mut data, 0x100, &1u32.to_le_bytes());
+        write(&mut data, 0x108, &[0x86, 0x80, 0x02, 0xB0, 0x01, 0x00]);
+        write(&mut data, 0x120, &1u32.to_le_bytes());
+        write(&mut data, 0x124, &0x906E0u32.to_le_bytes());
+        write(&mut data, 0x128, &0xFFFF0u32.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn info_table() {
+        let data = acm();
+        let acm = Acm::new(&data).unwrap();
+        assert_eq!(acm.date(), (2019, 4, 25));
+        assert_eq!(acm.info_table().unwrap().version, 4);
+        assert_eq!(acm.chipset_ids().unwrap().len(), 1);
+        assert_eq!(acm.processor_ids().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn matches() {
+        let data = acm();
+        let acm = Acm::new(&data).unwrap();
+        assert_eq!(acm.matches_chipset(0x8086, 0xB002, 1), Ok(true));
+        assert_eq!(acm.matches_chipset(0x8086, 0xB002, 2), Ok(false));
+        assert_eq!(acm.matches_chipset(0x8086, 0xB003, 1), Ok(false));
+        assert_eq!(acm.matches_processor(0x906EA, 0), Ok(true));
+        assert_eq!(acm.matches_processor(0x806EC, 0), Ok(false));
+    }
+
+    #[test]
+    fn info_table_missing() {
+        let mut data = acm();
+        // No scratch space, so the table is not where the header says
+        write(&mut data, 0x7C, &0u32.to_le_bytes());
+        let acm = Acm::new(&data).unwrap();
+        assert!(acm.info_table().is_err());
+        assert!(acm.matches_chipset(0x8086, 0xB002, 1).is_err());
+    }
+}
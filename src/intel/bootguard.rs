@@ -0,0 +1,543 @@
+// SPDX-License-Identifier: MIT
+
+//! Boot Guard 1.0 Key Manifest and Boot Policy Manifest
+//!
+//! Boot Guard 2.0 and later manifests have variable key sizes and are only identified by version.
+
+use alloc::string::String;
+use core::mem;
+use plain::Plain;
+use sha2::{Digest, Sha256};
+
+use super::fit;
+
+pub const KEY_MANIFEST_TAG: [u8; 8] = *b"__KEYM__";
+pub const BOOT_POLICY_TAG: [u8; 8] = *b"__ACBP__";
+pub const IBB_TAG: [u8; 8] = *b"__IBBS__";
+pub const SIGNATURE_TAG: [u8; 8] = *b"__PMSG__";
+
+/// Structure version of Boot Guard 1.0 manifests
+pub const VERSION_1_0: u8 = 0x10;
+
+/// RSA key size in bits used by Boot Guard 1.0 manifests
+pub const KEY_SIZE: u16 = 2048;
+
+#[repr(packed)]
+pub struct Sha256Hash {
+    pub algorithm: u16,
+    pub size: u16,
+    pub hash: [u8; 32],
+}
+
+unsafe impl Plain for Sha256Hash {}
+
+#[repr(packed)]
+pub struct PublicKey {
+    pub version: u8,
+    /// Key size in bits
+    pub key_size: u16,
+    pub exponent: u32,
+    pub modulus: [u8; 256],
+}
+
+unsafe impl Plain for PublicKey {}
+
+#[repr(packed)]
+pub struct RsaSignature {
+    pub version: u8,
+    pub key_size: u16,
+    pub hash_algorithm: u16,
+    pub signature: [u8; 256],
+}
+
+unsafe impl Plain for RsaSignature {}
+
+#[repr(packed)]
+pub struct KeySignature {
+    pub version: u8,
+    pub key_algorithm: u16,
+    pub public_key: PublicKey,
+    pub signature_scheme: u16,
+    pub signature: RsaSignature,
+}
+
+impl KeySignature {
+    /// True if the key and signature have the size of this structure
+    pub fn valid(&self) -> bool {
+        let (key_size, signature_size) = (self.public_key.key_size, self.signature.key_size);
+        key_size == KEY_SIZE && signature_size == KEY_SIZE
+    }
+
+    /// SHA-256 hash of the public key modulus
+    pub fn key_hash(&self) -> [u8; 32] {
+        Sha256::digest(self.public_key.modulus).into()
+    }
+}
+
+unsafe impl Plain for KeySignature {}
+
+#[repr(packed)]
+pub struct KeyManifest {
+    pub tag: [u8; 8],
+    pub version: u8,
+    pub km_version: u8,
+    pub km_svn: u8,
+    pub km_id: u8,
+    /// Hash of the key that signs the Boot Policy Manifest
+    pub bp_key_hash: Sha256Hash,
+    pub signature: KeySignature,
+}
+
+impl KeyManifest {
+    pub fn valid(&self) -> bool {
+        self.tag == KEY_MANIFEST_TAG
+    }
+
+    /// SHA-256 hash of the OEM public key, which is fused into the chipset
+    pub fn key_hash(&self) -> [u8; 32] {
+        self.signature.key_hash()
+    }
+}
+
+unsafe impl Plain for KeyManifest {}
+
+#[repr(packed)]
+pub struct BootPolicyHeader {
+    pub tag: [u8; 8],
+    pub version: u8,
+    pub header_version: u8,
+    pub bpm_version: u8,
+    pub bp_svn: u8,
+    pub acm_svn: u8,
+    pub reserved: u8,
+    pub nem_data_size: u16,
+}
+
+impl BootPolicyHeader {
+    pub fn valid(&self) -> bool {
+        self.tag == BOOT_POLICY_TAG
+    }
+}
+
+unsafe impl Plain for BootPolicyHeader {}
+
+#[repr(packed)]
+pub struct IbbElement {
+    pub tag: [u8; 8],
+    pub version: u8,
+    pub reserved0: u16,
+    pub reserved1: u8,
+    pub flags: u32,
+    pub mch_bar: u64,
+    pub vtd_bar: u64,
+    pub pmrl_base: u32,
+    pub pmrl_limit: u32,
+    pub reserved2: u64,
+    pub reserved3: u64,
+    pub post_ibb_hash: Sha256Hash,
+    pub entry_point: u32,
+    /// Expected hash of the IBB segments
+    pub digest: Sha256Hash,
+    pub segment_count: u8,
+}
+
+unsafe impl Plain for IbbElement {}
+
+#[repr(packed)]
+pub struct IbbSegment {
+    pub reserved: u16,
+    /// Bit 0 set if the segment is not hashed
+    pub flags: u16,
+    pub base: u32,
+    pub size: u32,
+}
+
+impl IbbSegment {
+    pub fn hashed(&self) -> bool {
+        self.flags & 1 == 0
+    }
+}
+
+unsafe impl Plain for IbbSegment {}
+
+/// Boot Policy Manifest with its IBB element and signature
+pub struct BootPolicyManifest<'a> {
+    pub header: &'a BootPolicyHeader,
+    pub ibb: &'a IbbElement,
+    pub segments: &'a [IbbSegment],
+    pub signature: Option<&'a KeySignature>,
+}
+
+impl<'a> BootPolicyManifest<'a> {
+    pub fn new(data: &'a [u8]) -> Result<BootPolicyManifest<'a>, String> {
+        let header = plain::from_bytes::<BootPolicyHeader>(data).map_err(|err| {
+            format!("Boot Policy Manifest invalid: {:?}", err)
+        })?;
+        if ! header.valid() {
+            return Err(String::from("Boot Policy Manifest tag not found"));
+        }
+        if header.version != VERSION_1_0 {
+            return Err(format!("Boot Policy Manifest version {:#X} not supported", header.version));
+        }
+
+        let ibb_offset = mem::size_of::<BootPolicyHeader>();
+        let ibb = plain::from_bytes::<IbbElement>(&data[ibb_offset..]).map_err(|err| {
+            format!("IBB element invalid: {:?}", err)
+        })?;
+        if ibb.tag != IBB_TAG {
+            return Err(String::from("IBB element tag not found"));
+        }
+
+        let segments_offset = ibb_offset + mem::size_of::<IbbElement>();
+        let segments = plain::slice_from_bytes_len::<IbbSegment>(
+            &data[segments_offset.min(data.len())..],
+            ibb.segment_count as usize
+        ).map_err(|err| {
+            format!("IBB segments invalid: {:?}", err)
+        })?;
+
+        // Other elements have varying sizes, so the signature element is found by its tag
+        let elements = segments_offset + mem::size_of_val(segments);
+        let signature = data[elements..].windows(8).position(|window| window == SIGNATURE_TAG).and_then(|i| {
+            // The tag is followed by the element version
+            plain::from_bytes::<KeySignature>(data.get(elements + i + 9..)?).ok()
+        }).filter(|signature| signature.valid());
+
+        Ok(BootPolicyManifest {
+            header,
+            ibb,
+            segments,
+            signature,
+        })
+    }
+
+    /// SHA-256 hash of the public key that signed this manifest
+    pub fn key_hash(&self) -> Option<[u8; 32]> {
+        self.signature.map(|signature| signature.key_hash())
+    }
+
+    /// Hash the IBB segments of a BIOS region mapped just below 4 GiB
+    pub fn ibb_hash(&self, bios: &[u8]) -> Result<[u8; 32], String> {
+        let mut hasher = Sha256::new();
+        for segment in self.segments.iter().filter(|segment| segment.hashed()) {
+            let (base, size) = (segment.base, segment.size);
+            let start = fit::translate(bios.len(), base as u64).ok_or_else(|| {
+                format!("IBB segment {:#X} outside of BIOS region", base)
+            })?;
+            let end = start + size as usize;
+            if end > bios.len() {
+                return Err(format!("IBB segment {:#X} size {:#X} beyond BIOS region", base, size));
+            }
+            hasher.update(&bios[start..end]);
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    /// True if the IBB segments of a BIOS region match the digest in the manifest
+    pub fn ibb_hash_valid(&self, bios: &[u8]) -> Result<bool, String> {
+        Ok(self.ibb_hash(bios)? == self.ibb.digest.hash)
+    }
+}
+
+/// Boot Guard manifests of a BIOS region
+pub struct BootGuard<'a> {
+    /// Structure version of the manifests
+    pub version: u8,
+    /// Key Manifest, if present and supported
+    pub key_manifest: Option<&'a KeyManifest>,
+    /// Boot Policy Manifest, if present and supported
+    pub boot_policy: Option<BootPolicyManifest<'a>>,
+}
+
+impl<'a> BootGuard<'a> {
+    /// Find the manifests through the FIT, or by their tags if there is no FIT
+    pub fn new(bios: &'a [u8]) -> Result<Option<BootGuard<'a>>, String> {
+        let mut km_offset = None;
+        let mut bpm_offset = None;
+
+        if let Some(fit) = fit::Fit::new(bios)? {
+            for item in fit.items() {
+                match item.entry.kind() {
+                    fit::EntryKind::KeyManifest => km_offset = item.offset,
+                    fit::EntryKind::BootPolicyManifest => bpm_offset = item.offset,
+                    _ => (),
+                }
+            }
+        }
+
+        let find = |tag: &[u8; 8]| bios.windows(8).position(|window| window == tag);
+        let km_offset = km_offset.or_else(|| find(&KEY_MANIFEST_TAG));
+        let bpm_offset = bpm_offset.or_else(|| find(&BOOT_POLICY_TAG));
+
+        if km_offset.is_none() && bpm_offset.is_none() {
+            return Ok(None);
+        }
+
+        // Manifests other than 1.0 are reported without being parsed
+        let versions = km_offset.iter().chain(bpm_offset.iter()).filter_map(|&offset| bios.get(offset + 8));
+        for &version in versions {
+            if version != VERSION_1_0 {
+                return Ok(Some(BootGuard {
+                    version,
+                    key_manifest: None,
+                    boot_policy: None,
+                }));
+            }
+        }
+
+        let key_manifest = match km_offset {
+            Some(offset) => {
+                let km = plain::from_bytes::<KeyManifest>(&bios[offset..]).map_err(|err| {
+                    format!("Key Manifest invalid: {:?}", err)
+                })?;
+                if ! km.valid() {
+                    return Err(String::from("Key Manifest tag not found"));
+                }
+                if ! km.signature.valid() {
+                    let key_size = km.signature.public_key.key_size;
+                    return Err(format!("Key Manifest key size {} not supported", key_size));
+                }
+                Some(km)
+            },
+            None => None,
+        };
+
+        let boot_policy = match bpm_offset {
+            Some(offset) => Some(BootPolicyManifest::new(&bios[offset..])?),
+            None => None,
+        };
+
+        Ok(Some(BootGuard {
+            version: VERSION_1_0,
+            key_manifest,
+            boot_policy,
+        }))
+    }
+
+    /// True if the manifests are Boot Guard 1.0 and were parsed
+    pub fn supported(&self) -> bool {
+        self.version == VERSION_1_0
+    }
+
+    /// True if the Boot Policy Manifest is signed by the key in the Key Manifest
+    pub fn boot_policy_key_valid(&self) -> Option<bool> {
+        let km = self.key_manifest?;
+        let key_hash = self.boot_policy.as_ref()?.key_hash()?;
+        Some(key_hash == km.bp_key_hash.hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// SHA-256 of 256 bytes of 0xA5, the KM key modulus
+    const KM_KEY_HASH: &str = "2c41a1dd584e3773b95674841b685f36c76b48ec4db75863372c2fd6e19a61ce";
+    /// SHA-256 of 256 bytes of 0x5A, the BPM key modulus
+    const BPM_KEY_HASH: &str = "8bfe96b7ab7217459a0d2f0b4b020a21e5976fec991eba4803711536093ca1b2";
+    /// SHA-256 of 0x100 bytes of 0xAB, the hashed IBB segment
+    const IBB_HASH: &str = "1080e279b51b8594a78556e2fdb4dfe9ca82ac2fbab5007de2bac4213c2e1f92";
+
+    const KM_OFFSET: usize = 0x0;
+    const BPM_OFFSET: usize = 0x400;
+    const FIT_OFFSET: usize = 0x1000;
+
+    fn hash(hex: &str) -> [u8; 32] {
+        let mut hash = [0; 32];
+        for (i, b) in hash.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        hash
+    }
+
+    fn write_key_signature(data: &mut [u8], offset: usize, modulus: u8) {
+        data[offset] = 0x10;
+        data[offset + 1..offset + 3].copy_from_slice(&0x0001u16.to_le_bytes());
+        data[offset + 3] = 0x10;
+        data[offset + 4..offset + 6].copy_from_slice(&KEY_SIZE.to_le_bytes());
+        data[offset + 6..offset + 10].copy_from_slice(&0x0001_0001u32.to_le_bytes());
+        data[offset + 10..offset + 266].iter_mut().for_each(|b| *b = modulus);
+        data[offset + 266..offset + 268].copy_from_slice(&0x0014u16.to_le_bytes());
+        data[offset + 268] = 0x10;
+        data[offset + 269..offset + 271].copy_from_slice(&KEY_SIZE.to_le_bytes());
+        data[offset + 271..offset + 273].copy_from_slice(&0x000Bu16.to_le_bytes());
+        data[offset + 273..offset + 529].iter_mut().for_each(|b| *b = 0x33);
+    }
+
+    fn write_fit_entry(data: &mut [u8], offset: usize, address: u64, size: u32, kind: u8) {
+        data[offset..offset + 8].copy_from_slice(&address.to_le_bytes());
+        data[offset + 8..offset + 12].copy_from_slice(&size.to_le_bytes());
+        data[offset + 12..offset + 16].copy_from_slice(&[0x00, 0x01, kind, 0x00]);
+    }
+
+    /// 8 KiB BIOS region at 0xFFFFE000 with a KM, a BPM listing a hashed and an unhashed IBB
+    /// segment, and a FIT pointing to both manifests
+    fn bios() -> Vec<u8> {
+        let mut data = vec![0xFF; 0x2000];
+
+        // Key Manifest, authorizing the BPM key
+        let km = KM_OFFSET;
+        data[km..km + 8].copy_from_slice(&KEY_MANIFEST_TAG);
+        data[km + 8..km + 12].copy_from_slice(&[VERSION_1_0, 0x01, 0x02, 0x03]);
+        data[km + 12..km + 16].copy_from_slice(&[0x0B, 0x00, 0x20, 0x00]);
+        data[km + 16..km + 48].copy_from_slice(&hash(BPM_KEY_HASH));
+        write_key_signature(&mut data, km + 48, 0xA5);
+
+        // Boot Policy Manifest header
+        let bpm = BPM_OFFSET;
+        data[bpm..bpm + 8].copy_from_slice(&BOOT_POLICY_TAG);
+        data[bpm + 8..bpm + 16].copy_from_slice(&[VERSION_1_0, 0x01, 0x04, 0x05, 0x06, 0x00, 0x00, 0x00]);
+
+        // IBB element
+        let ibb = bpm + 0x10;
+        data[ibb..ibb + 133].iter_mut().for_each(|b| *b = 0);
+        data[ibb..ibb + 8].copy_from_slice(&IBB_TAG);
+        data[ibb + 8] = VERSION_1_0;
+        data[ibb + 96..ibb + 100].copy_from_slice(&[0x0B, 0x00, 0x20, 0x00]);
+        data[ibb + 100..ibb + 132].copy_from_slice(&hash(IBB_HASH));
+        data[ibb + 132] = 2;
+
+        // IBB segments, the second one is not hashed
+        let segments = ibb + 133;
+        data[segments..segments + 12].copy_from_slice(&[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0xF8, 0xFF, 0xFF, 0x00, 0x01, 0x00, 0x00,
+        ]);
+        data[segments + 12..segments + 24].copy_from_slice(&[
+            0x00, 0x00, 0x01, 0x00, 0x00, 0xF9, 0xFF, 0xFF, 0x00, 0x01, 0x00, 0x00,
+        ]);
+
+        // Signature element, signed by the BPM key
+        let signature = segments + 24;
+        data[signature..signature + 8].copy_from_slice(&SIGNATURE_TAG);
+        data[signature + 8] = VERSION_1_0;
+        write_key_signature(&mut data, signature + 9, 0x5A);
+
+        // IBB contents
+        data[0x1800..0x1900].iter_mut().for_each(|b| *b = 0xAB);
+        data[0x1900..0x1A00].iter_mut().for_each(|b| *b = 0xCD);
+
+        // FIT
+        data[0x1FC0..0x1FC8].copy_from_slice(&(0xFFFF_E000 + FIT_OFFSET as u64).to_le_bytes());
+        write_fit_entry(&mut data, FIT_OFFSET, fit::SIGNATURE, 3, 0x00);
+        write_fit_entry(&mut data, FIT_OFFSET + 0x10, 0xFFFF_E000 + KM_OFFSET as u64, 0, 0x0B);
+        write_fit_entry(&mut data, FIT_OFFSET + 0x20, 0xFFFF_E000 + BPM_OFFSET as u64, 0, 0x0C);
+        data
+    }
+
+    #[test]
+    fn key_manifest() {
+        let data = bios();
+        let boot_guard = BootGuard::new(&data).unwrap().unwrap();
+        assert!(boot_guard.supported());
+
+        let km = boot_guard.key_manifest.unwrap();
+        assert_eq!((km.km_version, km.km_svn, km.km_id), (0x01, 0x02, 0x03));
+        assert!(km.signature.valid());
+        assert_eq!(km.key_hash(), hash(KM_KEY_HASH));
+        assert_eq!(km.bp_key_hash.hash, hash(BPM_KEY_HASH));
+        assert_eq!(boot_guard.boot_policy_key_valid(), Some(true));
+    }
+
+    #[test]
+    fn boot_policy_manifest() {
+        let data = bios();
+        let bpm = BootPolicyManifest::new(&data[BPM_OFFSET..]).unwrap();
+        let header = bpm.header;
+        assert_eq!((header.bpm_version, header.bp_svn, header.acm_svn), (0x04, 0x05, 0x06));
+
+        let segments: Vec<_> = bpm.segments.iter().map(|segment| {
+            (segment.base, segment.size, segment.hashed())
+        }).collect();
+        assert_eq!(segments, vec![(0xFFFF_F800, 0x100, true), (0xFFFF_F900, 0x100, false)]);
+        assert_eq!(bpm.key_hash(), Some(hash(BPM_KEY_HASH)));
+    }
+
+    #[test]
+    fn ibb_hash() {
+        let mut data = bios();
+        let bpm = BootPolicyManifest::new(&data[BPM_OFFSET..]).unwrap();
+        assert_eq!(bpm.ibb_hash(&data), Ok(hash(IBB_HASH)));
+        assert_eq!(bpm.ibb_hash_valid(&data), Ok(true));
+
+        // The unhashed segment can change, the hashed one can not
+        data[0x1900] = 0;
+        let bpm_data = data.clone();
+        let bpm = BootPolicyManifest::new(&bpm_data[BPM_OFFSET..]).unwrap();
+        assert_eq!(bpm.ibb_hash_valid(&data), Ok(true));
+        data[0x1800] = 0;
+        assert_eq!(bpm.ibb_hash_valid(&data), Ok(false));
+
+        // Segments must be mapped inside the BIOS region
+        assert_eq!(bpm.ibb_hash(&data[..0x400]), Err(String::from("IBB segment 0xFFFFF800 outside of BIOS region")));
+        let mut segment = bios();
+        segment[BPM_OFFSET + 0x10 + 133 + 8] = 0x01;
+        segment[BPM_OFFSET + 0x10 + 133 + 9] = 0x10;
+        let bpm = BootPolicyManifest::new(&segment[BPM_OFFSET..]).unwrap();
+        assert_eq!(bpm.ibb_hash(&segment), Err(String::from("IBB segment 0xFFFFF800 size 0x1001 beyond BIOS region")));
+    }
+
+    #[test]
+    fn scan() {
+        // Without a FIT, the manifests are found by their tags
+        let mut data = bios();
+        data[0x1FC0..0x1FC8].copy_from_slice(&[0xFF; 8]);
+        let boot_guard = BootGuard::new(&data).unwrap().unwrap();
+        assert!(boot_guard.key_manifest.is_some());
+        assert!(boot_guard.boot_policy.is_some());
+
+        let data = vec![0xFF; 0x2000];
+        assert!(BootGuard::new(&data).unwrap().is_none());
+    }
+
+    #[test]
+    fn unsupported() {
+        // Boot Guard 2.0 manifests are reported without being parsed
+        let mut data = bios();
+        data[KM_OFFSET + 8] = 0x21;
+        let boot_guard = BootGuard::new(&data).unwrap().unwrap();
+        assert!(!boot_guard.supported());
+        assert_eq!(boot_guard.version, 0x21);
+        assert!(boot_guard.key_manifest.is_none());
+        assert!(boot_guard.boot_policy.is_none());
+
+        let mut data = bios();
+        data[BPM_OFFSET + 8] = 0x23;
+        assert_eq!(BootGuard::new(&data).unwrap().unwrap().version, 0x23);
+        assert_eq!(
+            BootPolicyManifest::new(&data[BPM_OFFSET..]).err(),
+            Some(String::from("Boot Policy Manifest version 0x23 not supported"))
+        );
+
+        // A 3072 bit key does not fit the 1.0 structures
+        let mut data = bios();
+        data[KM_OFFSET + 52..KM_OFFSET + 54].copy_from_slice(&3072u16.to_le_bytes());
+        assert_eq!(BootGuard::new(&data).err(), Some(String::from("Key Manifest key size 3072 not supported")));
+
+        let mut data = bios();
+        let signature = BPM_OFFSET + 0x10 + 133 + 24 + 9;
+        data[signature + 4..signature + 6].copy_from_slice(&3072u16.to_le_bytes());
+        let bpm = BootPolicyManifest::new(&data[BPM_OFFSET..]).unwrap();
+        assert!(bpm.signature.is_none());
+    }
+
+    #[test]
+    fn invalid() {
+        let mut data = bios();
+        data[BPM_OFFSET + 0x10] = 0;
+        assert_eq!(BootGuard::new(&data).err(), Some(String::from("IBB element tag not found")));
+
+        let mut data = bios();
+        data[KM_OFFSET] = 0;
+        assert_eq!(BootGuard::new(&data).err(), Some(String::from("Key Manifest tag not found")));
+
+        let data = bios();
+        assert!(BootPolicyManifest::new(&data[BPM_OFFSET..BPM_OFFSET + 0x20]).err().unwrap().starts_with("IBB element invalid"));
+        assert_eq!(
+            BootPolicyManifest::new(&data[KM_OFFSET..]).err(),
+            Some(String::from("Boot Policy Manifest tag not found"))
+        );
+    }
+}
@@ -0,0 +1,188 @@
+// SPDX-License-Identifier: MIT
+
+use core::fmt;
+
+use super::family::Family;
+use super::flash;
+
+/// PCH generation that a flash descriptor was built for
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Chipset {
+    /// ICH8, ICH9 and ICH10
+    Ich,
+    /// 5 series
+    IbexPeak,
+    /// 6 and 7 series
+    CougarPoint,
+    /// 8 and 9 series
+    LynxPoint,
+    /// 100 and 200 series
+    SunrisePoint,
+    /// 300 and 400 series
+    CannonPoint,
+    /// 500 series
+    TigerLake,
+    /// 600 and 700 series
+    AlderLake,
+}
+
+impl fmt::Display for Chipset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Chipset::Ich => "ICH8/9/10",
+            Chipset::IbexPeak => "Ibex Peak",
+            Chipset::CougarPoint => "Cougar Point",
+            Chipset::LynxPoint => "Lynx Point",
+            Chipset::SunrisePoint => "Sunrise Point",
+            Chipset::CannonPoint => "Cannon Point",
+            Chipset::TigerLake => "Tiger Lake",
+            Chipset::AlderLake => "Alder Lake",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Confidence {
+    /// Only the descriptor was available, or the ME firmware is for another descriptor version
+    Low,
+    /// The descriptor and the ME agree on the descriptor version but not on the generation,
+    /// which is taken from the ME
+    Medium,
+    /// The ME generation is one that the descriptor allows
+    High,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChipsetGuess {
+    pub chipset: Chipset,
+    pub confidence: Confidence,
+}
+
+impl ChipsetGuess {
+    /// Combine the range of generations allowed by the descriptor with the generation of the ME
+    /// firmware
+    pub fn new(version: flash::Version, descriptor: (Chipset, Chipset), me: Option<Chipset>) -> Self {
+        let (first, last) = descriptor;
+        let me = match me {
+            Some(some) => some,
+            None => return ChipsetGuess {
+                chipset: first,
+                confidence: Confidence::Low,
+            },
+        };
+
+        let me_version = if me >= Chipset::SunrisePoint {
+            flash::Version::V2
+        } else {
+            flash::Version::V1
+        };
+
+        if me >= first && me <= last {
+            ChipsetGuess {
+                chipset: me,
+                confidence: Confidence::High,
+            }
+        } else if me_version == version {
+            ChipsetGuess {
+                chipset: me,
+                confidence: Confidence::Medium,
+            }
+        } else {
+            ChipsetGuess {
+                chipset: first,
+                confidence: Confidence::Low,
+            }
+        }
+    }
+}
+
+/// Chipset generation that shipped with a major ME or CSME firmware version. TXE, SPS and
+/// Ignition firmware use their own version numbers and return None.
+pub fn from_me_version(family: Family, major: u16) -> Option<Chipset> {
+    match family {
+        Family::Me | Family::Csme => (),
+        Family::Txe | Family::Sps | Family::Ignition => return None,
+    }
+
+    match major {
+        2..=5 => Some(Chipset::Ich),
+        6 => Some(Chipset::IbexPeak),
+        7..=8 => Some(Chipset::CougarPoint),
+        9..=10 => Some(Chipset::LynxPoint),
+        11 => Some(Chipset::SunrisePoint),
+        12..=14 => Some(Chipset::CannonPoint),
+        15 => Some(Chipset::TigerLake),
+        16 => Some(Chipset::AlderLake),
+        _ => None,
+    }
+}
+
+/// Range of generations allowed by the descriptor map and component table, using the same rules
+/// as flashrom. IFDv1 descriptors give a single generation. IFDv2 descriptors are told apart by
+/// the ICC register init base, and by the read clock, which is fixed at 17 MHz on Sunrise Point
+/// and repurposed from Cannon Point. Tiger Lake and later cannot be told apart.
+pub fn from_descriptor(descriptor: &flash::Descriptor, component: &flash::Component, version: flash::Version) -> (Chipset, Chipset) {
+    let isl = (descriptor.map1 >> 24) & 0xff;
+    let fmsba = descriptor.map2 & 0xff;
+    let msl = (descriptor.map2 >> 8) & 0xff;
+    let iccriba = (descriptor.map2 >> 16) & 0xff;
+    let read_clock = (component.comp >> 17) & 0x7;
+
+    let chipset = if version == flash::Version::V2 {
+        if iccriba < 0x34 && read_clock == 6 {
+            Chipset::SunrisePoint
+        } else if iccriba <= 0x34 {
+            Chipset::CannonPoint
+        } else {
+            return (Chipset::TigerLake, Chipset::AlderLake);
+        }
+    } else if iccriba == 0 {
+        if isl <= 10 {
+            Chipset::Ich
+        } else {
+            Chipset::IbexPeak
+        }
+    } else if iccriba < 0x31 && fmsba < 0x30 {
+        if msl <= 1 && isl <= 18 {
+            Chipset::CougarPoint
+        } else {
+            Chipset::LynxPoint
+        }
+    } else {
+        Chipset::IbexPeak
+    };
+
+    (chipset, chipset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guess_confidence() {
+        let v2 = flash::Version::V2;
+        let later = (Chipset::TigerLake, Chipset::AlderLake);
+
+        let guess = ChipsetGuess::new(v2, later, Some(Chipset::AlderLake));
+        assert_eq!(guess, ChipsetGuess { chipset: Chipset::AlderLake, confidence: Confidence::High });
+
+        let guess = ChipsetGuess::new(v2, later, Some(Chipset::CannonPoint));
+        assert_eq!(guess, ChipsetGuess { chipset: Chipset::CannonPoint, confidence: Confidence::Medium });
+
+        let guess = ChipsetGuess::new(v2, later, Some(Chipset::LynxPoint));
+        assert_eq!(guess, ChipsetGuess { chipset: Chipset::TigerLake, confidence: Confidence::Low });
+
+        let guess = ChipsetGuess::new(v2, later, None);
+        assert_eq!(guess, ChipsetGuess { chipset: Chipset::TigerLake, confidence: Confidence::Low });
+    }
+
+    #[test]
+    fn me_version_family() {
+        assert_eq!(from_me_version(Family::Csme, 15), Some(Chipset::TigerLake));
+        assert_eq!(from_me_version(Family::Me, 9), Some(Chipset::LynxPoint));
+        assert_eq!(from_me_version(Family::Sps, 4), None);
+        assert_eq!(from_me_version(Family::Txe, 3), None);
+    }
+}
@@ -0,0 +1,397 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem;
+
+use super::cpd::{self, Cpd};
+use super::fpt::{self, Fpt};
+use super::manifest::{Manifest, ManifestKind};
+
+/// Modules of the FTPR partition that ME 6 to 10 cannot boot without
+pub const GEN2_MODULES: &[&str] = &["ROMP", "BUP"];
+
+/// Modules of the FTPR partition that CSE firmware cannot boot without
+pub const GEN3_MODULES: &[&str] = &["rbe", "kernel", "syslib", "bup"];
+
+/// Options for removing non-essential ME firmware
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    /// Move FTPR to directly after the partition table, only supported for CSE firmware
+    pub relocate: bool,
+    /// Shrink the ME region to the remaining firmware, giving the space to BIOS
+    pub shrink: bool,
+    /// Set the HAP or ME disable straps
+    pub disable: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            relocate: false,
+            shrink: false,
+            disable: true,
+        }
+    }
+}
+
+/// What was removed from the ME region
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub removed_partitions: Vec<String>,
+    pub removed_modules: Vec<String>,
+    /// Bytes at the start of the ME region still in use, aligned to 4 KiB
+    pub used: usize,
+}
+
+fn erase(data: &mut [u8], start: usize, len: usize) {
+    let end = start.saturating_add(len).min(data.len());
+    if start < end {
+        for b in data[start..end].iter_mut() {
+            *b = 0xFF;
+        }
+    }
+}
+
+fn overlaps(a: (usize, usize), b: (usize, usize)) -> bool {
+    a.0 < b.0 + b.1 && b.0 < a.0 + a.1
+}
+
+fn word(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Huffman chunk lookup table (LLUT) of ME 6 to 10, shared by all Huffman modules
+struct Llut {
+    /// Address of the first chunk
+    base: u32,
+    chunk_size: u32,
+    /// Start and end of each chunk in the ME region, zero if the chunk is not present
+    chunks: Vec<(usize, usize)>,
+}
+
+impl Llut {
+    /// Parse the table at an offset of the ME region, like me_cleaner
+    fn new(me: &[u8], offset: usize) -> Result<Llut, String> {
+        let header = me.get(offset..offset + 0x40).filter(|header| header.starts_with(b"LLUT")).ok_or_else(|| {
+            String::from("Huffman modules found, but LLUT is not present")
+        })?;
+
+        let count = word(header, 0x04) as usize;
+        let base = word(header, 0x08).wrapping_add(0x1000_0000);
+        let stream_end = word(header, 0x10) as usize + word(header, 0x14) as usize;
+        let chunk_size = word(header, 0x30);
+        if chunk_size == 0 {
+            return Err(String::from("LLUT chunk size invalid"));
+        }
+
+        let table = count.checked_mul(4).and_then(|len| me.get(offset + 0x40..offset + 0x40 + len)).ok_or_else(|| {
+            format!("LLUT truncated: {} chunks", count)
+        })?;
+
+        // Chunks are not stored in order, each one ends where the next one in flash begins
+        let starts: Vec<usize> = table.chunks(4).map(|chunk| {
+            if chunk[3] == 0x80 {
+                0
+            } else {
+                chunk[0] as usize | (chunk[1] as usize) << 8 | (chunk[2] as usize) << 16
+            }
+        }).collect();
+        let mut ends: Vec<usize> = starts.iter().cloned().filter(|&start| start != 0).collect();
+        ends.push(stream_end);
+        ends.sort_unstable();
+        ends.dedup();
+
+        let chunks = starts.iter().map(|&start| {
+            if start == 0 {
+                (0, 0)
+            } else {
+                let next = ends.binary_search(&start).map_or(None, |i| ends.get(i + 1));
+                (start, next.cloned().unwrap_or(start))
+            }
+        }).collect();
+
+        Ok(Llut { base, chunk_size, chunks })
+    }
+
+    /// Chunks holding a module loaded at `base`
+    fn module_chunks(&self, base: u32, size: usize) -> &[(usize, usize)] {
+        let first = (base.wrapping_sub(self.base) / self.chunk_size) as usize;
+        let last = first.saturating_add(size / self.chunk_size as usize);
+        let len = self.chunks.len();
+        &self.chunks[first.min(len)..last.saturating_add(1).min(len)]
+    }
+}
+
+/// Modules to remove from an FTPR partition
+struct Removal {
+    names: Vec<String>,
+    /// Offsets and lengths in the ME region of the removed data
+    ranges: Vec<(usize, usize)>,
+    /// End of the data in the ME region that has to be kept
+    end: usize,
+}
+
+/// Removable modules of an ME 6 to 10 FTPR partition
+///
+/// Huffman modules share the chunks of the lookup table, so only chunks that no essential module
+/// uses are removed. Modules with an unknown compression are kept.
+fn gen2_modules(me: &[u8], ftpr: (usize, usize), manifest: &Manifest) -> Result<Removal, String> {
+    let mut names = Vec::new();
+    let mut ranges = Vec::new();
+    let mut end = ftpr.0 + ftpr.1;
+
+    let mut llut: Option<Llut> = None;
+    let mut kept_chunks = Vec::new();
+
+    for header in manifest.module_headers()? {
+        let name = header.name();
+        let essential = GEN2_MODULES.contains(&name);
+        match header.compression() {
+            Some(cpd::Compression::Huffman) => {
+                let table = match llut {
+                    Some(ref table) => table,
+                    None => llut.insert(Llut::new(me, ftpr.0 + header.offset())?),
+                };
+                let chunks = table.module_chunks(header.base(), header.uncompressed_size());
+                if essential {
+                    kept_chunks.extend(chunks.iter().cloned().filter(|chunk| chunk.0 != 0));
+                } else {
+                    names.push(String::from(name));
+                }
+            },
+            Some(_) if ! essential => {
+                names.push(String::from(name));
+                ranges.push((ftpr.0 + header.offset(), header.size()));
+            },
+            _ => (),
+        }
+    }
+
+    if let Some(llut) = llut {
+        for &(start, chunk_end) in llut.chunks.iter() {
+            let removable = ! kept_chunks.iter().any(|kept| {
+                overlaps((start, chunk_end - start), (kept.0, kept.1 - kept.0))
+            });
+            if chunk_end > start && removable {
+                ranges.push((start, chunk_end - start));
+            }
+        }
+        end = kept_chunks.iter().fold(end, |end, chunk| end.max(chunk.1));
+    }
+
+    Ok(Removal { names, ranges, end })
+}
+
+/// Removable modules of a CSE FTPR partition. Each module ends where the next entry begins, as
+/// Huffman modules have no reliable size.
+fn gen3_modules(cpd: &Cpd, ftpr: (usize, usize)) -> Removal {
+    let mut offsets: Vec<usize> = cpd.entries().iter().map(|entry| entry.offset()).collect();
+    offsets.sort_unstable();
+
+    let mut names = Vec::new();
+    let mut ranges = Vec::new();
+    for module in cpd.modules() {
+        if GEN3_MODULES.contains(&module.name) {
+            continue;
+        }
+        let end = offsets.iter().cloned().find(|&offset| offset > module.offset).unwrap_or(ftpr.1).min(ftpr.1);
+        names.push(String::from(module.name));
+        ranges.push((ftpr.0 + module.offset, end.saturating_sub(module.offset)));
+    }
+    Removal { names, ranges, end: ftpr.0 + ftpr.1 }
+}
+
+/// Remove every partition but FTPR and every non-essential FTPR module from an ME region
+///
+/// The entries of removed modules are kept. On ME 6 to 10, Huffman compressed modules are
+/// removed through the shared lookup table, keeping the chunks that essential modules use.
+pub fn clean(me: &mut [u8], relocate: bool) -> Result<Report, String> {
+    let mut report = Report::default();
+
+    let (fpt_offset, entries_offset, entries, ftpr_index) = {
+        let fpt = Fpt::new(me)?;
+        let entries: Vec<(String, u32, u32, bool)> = fpt.entries().iter().map(|entry| {
+            (String::from(entry.name()), entry.offset, entry.length, entry.valid() && ! entry.disabled())
+        }).collect();
+        let ftpr_index = entries.iter().position(|entry| entry.0 == "FTPR" && entry.3).ok_or_else(|| {
+            String::from("FTPR partition not found")
+        })?;
+        (fpt.offset(), fpt.entries_offset(), entries, ftpr_index)
+    };
+
+    let mut ftpr = (entries[ftpr_index].1 as usize, entries[ftpr_index].2 as usize);
+    if ftpr.0 + ftpr.1 > me.len() {
+        return Err(format!("FTPR partition truncated: {:#X} > {:#X}", ftpr.0 + ftpr.1, me.len()));
+    }
+
+    // Removable modules are found before anything is changed
+    let (cse, removal) = {
+        let data = &me[ftpr.0..ftpr.0 + ftpr.1];
+        if data.starts_with(b"$CPD") {
+            (true, gen3_modules(&Cpd::new(data)?, ftpr))
+        } else {
+            let manifest = Manifest::new(data)?;
+            if manifest.kind() != ManifestKind::Mn2 {
+                return Err(format!("{:?} manifest not supported", manifest.kind()));
+            }
+            (false, gen2_modules(me, ftpr, &manifest)?)
+        }
+    };
+
+    if relocate && ! cse {
+        return Err(String::from("FTPR relocation only supported for CSE firmware"));
+    }
+
+    for (i, (name, offset, length, present)) in entries.iter().enumerate() {
+        let range = (*offset as usize, *length as usize);
+        if i == ftpr_index || ! present || overlaps(range, ftpr) || overlaps(range, (0, entries_offset)) {
+            continue;
+        }
+        erase(me, range.0, range.1);
+        report.removed_partitions.push(name.clone());
+    }
+
+    for &(offset, length) in removal.ranges.iter() {
+        erase(me, offset, length);
+    }
+    report.removed_modules = removal.names;
+
+    // Keep only the FTPR entry in the partition table
+    let entry_size = mem::size_of::<fpt::Entry>();
+    let ftpr_entry = me[entries_offset + ftpr_index * entry_size..][..entry_size].to_vec();
+    me[entries_offset..entries_offset + entry_size].copy_from_slice(&ftpr_entry);
+    erase(me, entries_offset + entry_size, (entries.len() - 1) * entry_size);
+    me[fpt_offset + 4..fpt_offset + 8].copy_from_slice(&1u32.to_le_bytes());
+
+    if relocate {
+        let new_offset = (entries_offset + entry_size + 0xFFF) & !0xFFF;
+        if new_offset < ftpr.0 {
+            me.copy_within(ftpr.0..ftpr.0 + ftpr.1, new_offset);
+            let stale = ftpr.0.max(new_offset + ftpr.1);
+            erase(me, stale, ftpr.0 + ftpr.1 - stale);
+            me[entries_offset + 8..entries_offset + 12].copy_from_slice(&(new_offset as u32).to_le_bytes());
+            ftpr.0 = new_offset;
+        }
+    }
+
+    let checksum = Fpt::new(me)?.checksum();
+    if Fpt::new(me)?.header().crc32() {
+        me[fpt_offset + 0x14..fpt_offset + 0x18].copy_from_slice(&checksum.to_le_bytes());
+    } else {
+        me[fpt_offset + 0x0B] = checksum as u8;
+    }
+
+    let end = if relocate { ftpr.0 + ftpr.1 } else { removal.end };
+    report.used = (end.max(entries_offset + entry_size) + 0xFFF) & !0xFFF;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn module(me: &mut [u8], index: usize, name: &str, base: u32, offset: u32, size: u32, compression: u32) {
+        let header = 0x1000 + 0x290 + index * 0x80;
+        for b in me[header..header + 0x80].iter_mut() {
+            *b = 0;
+        }
+        write(me, header, u32::from_le_bytes(*b"$MME"));
+        me[header + 4..header + 4 + name.len()].copy_from_slice(name.as_bytes());
+        write(me, header + 0x34, base);
+        write(me, header + 0x38, offset);
+        write(me, header + 0x3C, size);
+        write(me, header + 0x40, size);
+        write(me, header + 0x50, compression << 4);
+    }
+
+    /// ME 9 region with FTPR and NFTP partitions, and 0x80 byte module headers
+    fn gen2() -> Vec<u8> {
+        let mut me = vec![0xFF; 0x10000];
+
+        me[0x10..0x14].copy_from_slice(b"$FPT");
+        write(&mut me, 0x14, 2);
+        me[0x18..0x1C].copy_from_slice(&[0x20, 0x10, 0x20, 0x00]);
+        me[0x30..0x34].copy_from_slice(b"FTPR");
+        write(&mut me, 0x38, 0x1000);
+        write(&mut me, 0x3C, 0x6000);
+        write(&mut me, 0x4C, 0);
+        me[0x50..0x54].copy_from_slice(b"NFTP");
+        write(&mut me, 0x58, 0x8000);
+        write(&mut me, 0x5C, 0x1000);
+        write(&mut me, 0x6C, 0);
+
+        // Manifest with a 2048-bit key
+        write(&mut me, 0x1000, 4);
+        write(&mut me, 0x1004, 0xA1);
+        write(&mut me, 0x1008, 0x10000);
+        me[0x101C..0x1020].copy_from_slice(b"$MN2");
+        write(&mut me, 0x1020, 4);
+        write(&mut me, 0x1024, 9);
+        write(&mut me, 0x1078, 0x40);
+        write(&mut me, 0x107C, 1);
+
+        module(&mut me, 0, "ROMP", 0, 0x800, 0x100, 0);
+        module(&mut me, 1, "AMT", 0, 0x1000, 0x200, 2);
+        module(&mut me, 2, "BUP", 0x1000_0000, 0x2000, 0x1000, 1);
+        module(&mut me, 3, "KERNEL", 0x1000_2000, 0x2000, 0x1000, 1);
+        for b in me[0x1800..0x1900].iter_mut() {
+            *b = 0x11;
+        }
+        for b in me[0x2000..0x2200].iter_mut() {
+            *b = 0x22;
+        }
+
+        // Lookup table with three chunks present, out of order
+        me[0x3000..0x3004].copy_from_slice(b"LLUT");
+        write(&mut me, 0x3004, 4);
+        write(&mut me, 0x3008, 0);
+        write(&mut me, 0x3010, 0x4000);
+        write(&mut me, 0x3014, 0x2000);
+        write(&mut me, 0x3030, 0x1000);
+        write(&mut me, 0x3040, 0x4000);
+        write(&mut me, 0x3044, 0x4800);
+        write(&mut me, 0x3048, 0x5000);
+        write(&mut me, 0x304C, 0x8000_0000);
+        for b in me[0x4000..0x6000].iter_mut() {
+            *b = 0xAA;
+        }
+
+        for b in me[0x8000..0x9000].iter_mut() {
+            *b = 0x33;
+        }
+
+        me
+    }
+
+    #[test]
+    fn clean_gen2() {
+        let mut me = gen2();
+        let report = clean(&mut me, false).unwrap();
+
+        assert_eq!(report.removed_partitions, ["NFTP"]);
+        assert_eq!(report.removed_modules, ["AMT", "KERNEL"]);
+        assert_eq!(report.used, 0x7000);
+
+        assert!(me[0x1800..0x1900].iter().all(|&b| b == 0x11));
+        assert!(me[0x2000..0x2200].iter().all(|&b| b == 0xFF));
+        assert!(me[0x4000..0x5000].iter().all(|&b| b == 0xAA));
+        assert!(me[0x5000..0x6000].iter().all(|&b| b == 0xFF));
+        assert!(me[0x8000..0x9000].iter().all(|&b| b == 0xFF));
+
+        let fpt = Fpt::new(&me).unwrap();
+        let count = fpt.header().num_entries;
+        assert_eq!(count, 1);
+        assert!(fpt.checksum_valid());
+    }
+
+    #[test]
+    fn clean_gen2_relocate() {
+        let mut me = gen2();
+        assert!(clean(&mut me, true).is_err());
+    }
+}
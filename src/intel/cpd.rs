@@ -0,0 +1,369 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{mem, str};
+use plain::Plain;
+
+use super::{crc, lzma};
+
+/// Module Attributes extension in a `.met` file
+pub const EXT_MODULE_ATTRIBUTES: u32 = 0x0A;
+
+/// Code Partition Directory header, version 1
+#[repr(packed)]
+pub struct Header {
+    pub signature: [u8; 4],
+    pub num_entries: u32,
+    pub header_version: u8,
+    pub entry_version: u8,
+    pub header_length: u8,
+    pub checksum: u8,
+    pub partition_name: [u8; 4],
+}
+
+impl Header {
+    pub fn valid(&self) -> bool {
+        self.signature == *b"$CPD"
+    }
+}
+
+unsafe impl Plain for Header {}
+
+/// Code Partition Directory entry
+#[repr(packed)]
+pub struct Entry {
+    pub name: [u8; 12],
+    pub offset: u32,
+    pub length: u32,
+    pub reserved: u32,
+}
+
+impl Entry {
+    /// Entry name, such as `FTPR.man` or `kernel`
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(self.name.len());
+        str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+
+    /// Offset of the entry relative to the directory
+    pub fn offset(&self) -> usize {
+        (self.offset & 0x1FF_FFFF) as usize
+    }
+
+    /// True if the entry is marked as Huffman compressed
+    pub fn huffman(&self) -> bool {
+        self.offset & (1 << 25) != 0
+    }
+}
+
+unsafe impl Plain for Entry {}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    Huffman,
+    Lzma,
+}
+
+/// Code module listed in a Code Partition Directory
+pub struct Module<'a> {
+    pub name: &'a str,
+    /// Offset relative to the directory
+    pub offset: usize,
+    pub compression: Compression,
+    /// Size after decompression, if known from the metadata
+    pub uncompressed_size: Option<usize>,
+    pub data: &'a [u8],
+}
+
+impl Module<'_> {
+    /// Module contents after decompression
+    ///
+    /// Huffman compressed modules are not supported and return an error.
+    pub fn decompress(&self) -> Result<Vec<u8>, String> {
+        match self.compression {
+            Compression::None => Ok(self.data.to_vec()),
+            Compression::Lzma => lzma::decompress(self.data, self.uncompressed_size),
+            Compression::Huffman => Err(format!("{}: Huffman decompression not supported", self.name)),
+        }
+    }
+}
+
+/// Code Partition Directory at the start of a CSE code partition
+pub struct Cpd<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cpd<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Cpd<'a>, String> {
+        let header = plain::from_bytes::<Header>(data).map_err(|err| {
+            format!("$CPD header invalid: {:?}", err)
+        })?;
+
+        if ! header.valid() {
+            return Err(String::from("$CPD not found"));
+        }
+
+        let cpd = Cpd { data };
+        let end = cpd.entries_offset() + header.num_entries as usize * mem::size_of::<Entry>();
+        if end > data.len() {
+            return Err(format!("$CPD entries truncated: {:#X} > {:#X}", end, data.len()));
+        }
+
+        Ok(cpd)
+    }
+
+    /// The code partition containing the directory
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn header(&self) -> &'a Header {
+        plain::from_bytes(self.data).unwrap()
+    }
+
+    /// Name of the partition, such as `FTPR`
+    pub fn partition_name(&self) -> &'a str {
+        let name = &self.header().partition_name;
+        str::from_utf8(name).unwrap_or("").trim_end_matches('\0')
+    }
+
+    fn entries_offset(&self) -> usize {
+        let header = self.header();
+        if header.header_version >= 2 {
+            (header.header_length as usize).max(0x14)
+        } else {
+            (header.header_length as usize).max(mem::size_of::<Header>())
+        }
+    }
+
+    pub fn entries(&self) -> &'a [Entry] {
+        let count = self.header().num_entries as usize;
+        plain::slice_from_bytes_len(&self.data[self.entries_offset()..], count).unwrap()
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&'a Entry> {
+        self.entries().iter().find(|entry| entry.name() == name)
+    }
+
+    /// Contents of an entry, if it fits in the partition
+    pub fn entry_data(&self, entry: &Entry) -> Option<&'a [u8]> {
+        let start = entry.offset();
+        let end = start.checked_add(entry.length as usize)?;
+        self.data.get(start..end)
+    }
+
+    /// CRC32 stored in a version 2 header
+    pub fn crc32(&self) -> Option<u32> {
+        if self.header().header_version >= 2 {
+            let bytes = &self.data[0x10..0x14];
+            Some(bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24)
+        } else {
+            None
+        }
+    }
+
+    pub fn checksum_valid(&self) -> bool {
+        let end = self.entries_offset() + mem::size_of_val(self.entries());
+        if let Some(expected) = self.crc32() {
+            let mut bytes = self.data[..end].to_vec();
+            // CRC32 field is zeroed while computing the checksum
+            for b in &mut bytes[0x10..0x14] {
+                *b = 0;
+            }
+            crc::crc32(&bytes) == expected
+        } else {
+            self.data[..end].iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+        }
+    }
+
+    /// Compression and uncompressed size from the Module Attributes extension of `<name>.met`
+    fn module_attributes(&self, name: &str) -> Option<(Compression, usize)> {
+        let mut met_name = String::from(name);
+        met_name.push_str(".met");
+        let data = self.entry_data(self.entry(&met_name)?)?;
+
+        let mut i = 0;
+        while i + 8 <= data.len() {
+            let kind = u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+            let length = u32::from_le_bytes([data[i + 4], data[i + 5], data[i + 6], data[i + 7]]) as usize;
+            if length < 8 || i + length > data.len() {
+                break;
+            }
+            if kind == EXT_MODULE_ATTRIBUTES && length >= 0x10 {
+                let compression = match data[i + 8] {
+                    1 => Compression::Huffman,
+                    2 => Compression::Lzma,
+                    _ => Compression::None,
+                };
+                let size = u32::from_le_bytes([data[i + 12], data[i + 13], data[i + 14], data[i + 15]]);
+                return Some((compression, size as usize));
+            }
+            i += length;
+        }
+
+        None
+    }
+
+    /// Code modules, excluding manifest and metadata entries
+    pub fn modules(&self) -> Vec<Module<'a>> {
+        let mut modules = Vec::new();
+        for entry in self.entries() {
+            let name = entry.name();
+            if name.ends_with(".man") || name.ends_with(".met") {
+                continue;
+            }
+
+            let data = self.entry_data(entry).unwrap_or(&[]);
+            let (compression, uncompressed_size) = match self.module_attributes(name) {
+                Some((compression, size)) => (compression, Some(size)),
+                None => {
+                    let compression = if entry.huffman() {
+                        Compression::Huffman
+                    } else if data.first().is_some_and(|&b| b == 0x36 || b == 0x5D) {
+                        Compression::Lzma
+                    } else {
+                        Compression::None
+                    };
+                    (compression, None)
+                }
+            };
+
+            modules.push(Module {
+                name,
+                offset: entry.offset(),
+                compression,
+                uncompressed_size,
+                data,
+            });
+        }
+        modules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    /// Directory of FTPR with a manifest, an LZMA module described by its metadata,
+    /// an LZMA module detected by its header and a Huffman module
+    fn cpd(header_version: u8) -> Vec<u8> {
+        let header_length = if header_version >= 2 { 0x14 } else { 0x10 };
+        let mut data = vec![0; 0x140];
+        data[0x00..0x04].copy_from_slice(b"$CPD");
+        data[0x04] = 5;
+        data[0x08..0x0B].copy_from_slice(&[header_version, 1, header_length as u8]);
+        data[0x0C..0x10].copy_from_slice(b"FTPR");
+
+        let entries: [(&[u8], u32, u32); 5] = [
+            (b"FTPR.man", 0x100, 0x10),
+            (b"kernel", 0x110, 0x10),
+            (b"kernel.met", 0x120, 0x10),
+            (b"bup", 0x130, 0x08),
+            (b"syslib", 0x138 | 1 << 25, 0x08),
+        ];
+        for (i, (name, offset, length)) in entries.iter().enumerate() {
+            let entry = header_length + i * mem::size_of::<Entry>();
+            data[entry..entry + name.len()].copy_from_slice(name);
+            data[entry + 0x0C..entry + 0x10].copy_from_slice(&offset.to_le_bytes());
+            data[entry + 0x10..entry + 0x14].copy_from_slice(&length.to_le_bytes());
+        }
+
+        // Module Attributes extension, LZMA with 0x1234 bytes uncompressed
+        data[0x120..0x130].copy_from_slice(&[
+            0x0A, 0, 0, 0, 0x10, 0, 0, 0, 2, 0, 0, 0, 0x34, 0x12, 0, 0,
+        ]);
+        data[0x130] = 0x36;
+        data
+    }
+
+    #[test]
+    fn parse() {
+        let data = cpd(1);
+        let cpd = Cpd::new(&data).unwrap();
+        assert_eq!(cpd.partition_name(), "FTPR");
+        assert_eq!(cpd.entries().len(), 5);
+        assert_eq!(cpd.entry("kernel").map(|entry| entry.offset()), Some(0x110));
+        assert_eq!(cpd.entry_data(cpd.entry("FTPR.man").unwrap()), Some(&data[0x100..0x110]));
+        assert!(cpd.entry("syslib").unwrap().huffman());
+        assert_eq!(cpd.crc32(), None);
+
+        let modules = cpd.modules();
+        let summary: Vec<_> = modules.iter().map(|module| {
+            (module.name, module.offset, module.compression, module.uncompressed_size)
+        }).collect();
+        assert_eq!(summary, vec![
+            ("kernel", 0x110, Compression::Lzma, Some(0x1234)),
+            ("bup", 0x130, Compression::Lzma, None),
+            ("syslib", 0x138, Compression::Huffman, None),
+        ]);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let mut data = cpd(1);
+        assert!(Cpd::new(&data[..0x0C]).err().unwrap().starts_with("$CPD header invalid"));
+        assert_eq!(
+            Cpd::new(&data[..0x80]).err(),
+            Some(String::from("$CPD entries truncated: 0x88 > 0x80"))
+        );
+
+        // Entry past the end of the partition
+        data[0x10 + 0x18 + 0x10] = 0x40;
+        let cpd = Cpd::new(&data).unwrap();
+        assert_eq!(cpd.entry_data(cpd.entry("kernel").unwrap()), None);
+        assert_eq!(cpd.modules()[0].data, &[]);
+
+        data[0] = b'X';
+        assert_eq!(Cpd::new(&data).err(), Some(String::from("$CPD not found")));
+    }
+
+    #[test]
+    fn checksum_v1() {
+        // Byte sum of the header and entries must be zero
+        let mut data = cpd(1);
+        assert!(!Cpd::new(&data).unwrap().checksum_valid());
+        data[0x0B] = 0xDA;
+        assert!(Cpd::new(&data).unwrap().checksum_valid());
+        data[0x88] = 1;
+        assert!(Cpd::new(&data).unwrap().checksum_valid());
+        data[0x87] = 1;
+        assert!(!Cpd::new(&data).unwrap().checksum_valid());
+    }
+
+    #[test]
+    fn checksum_v2() {
+        // CRC32 of the header and entries with the CRC field zeroed
+        let mut data = cpd(2);
+        assert_eq!(Cpd::new(&data).unwrap().crc32(), Some(0));
+        assert!(!Cpd::new(&data).unwrap().checksum_valid());
+        data[0x10..0x14].copy_from_slice(&[0x7F, 0x03, 0x37, 0x27]);
+        let cpd = Cpd::new(&data).unwrap();
+        assert_eq!(cpd.crc32(), Some(0x2737_037F));
+        assert!(cpd.checksum_valid());
+        assert_eq!(cpd.modules().len(), 3);
+    }
+
+    fn module(compression: Compression, data: &[u8]) -> Module {
+        Module {
+            name: "test",
+            offset: 0,
+            compression,
+            uncompressed_size: None,
+            data,
+        }
+    }
+
+    #[test]
+    fn decompress() {
+        assert_eq!(module(Compression::None, b"ME").decompress(), Ok(b"ME".to_vec()));
+        assert_eq!(
+            module(Compression::Huffman, b"ME").decompress(),
+            Err(String::from("test: Huffman decompression not supported"))
+        );
+        assert!(module(Compression::Lzma, b"ME").decompress().is_err());
+    }
+}
@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: MIT
+
+use core::fmt;
+
+use super::manifest::{Manifest, ManifestKind};
+
+/// Kind of engine firmware in an ME region
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Family {
+    /// Management Engine 2 to 10
+    Me,
+    /// Converged Security and Management Engine, from ME 11
+    Csme,
+    /// Trusted Execution Engine on Atom platforms
+    Txe,
+    /// Server Platform Services
+    Sps,
+    /// Ignition firmware, a minimal CSME stub without a recovery partition
+    Ignition,
+}
+
+impl Family {
+    /// Guess the family from the partition names, manifest and region size
+    pub fn new(partitions: &[&str], manifest: Option<&Manifest>, len: usize) -> Option<Family> {
+        // SPS images have operational partitions in place of NFTP
+        if partitions.iter().any(|name| name.starts_with("OPR")) {
+            return Some(Family::Sps);
+        }
+
+        let manifest = manifest?;
+        let major = manifest.version().major;
+        match manifest.kind() {
+            ManifestKind::Legacy => Some(Family::Me),
+            ManifestKind::Mn2 => if major <= 2 {
+                Some(Family::Txe)
+            } else {
+                Some(Family::Me)
+            },
+            ManifestKind::Cse => if major < 11 {
+                Some(Family::Txe)
+            } else if len <= 0x100000 && ! partitions.contains(&"NFTP") {
+                Some(Family::Ignition)
+            } else {
+                Some(Family::Csme)
+            },
+        }
+    }
+}
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Family::Me => "ME",
+            Family::Csme => "CSME",
+            Family::Txe => "TXE",
+            Family::Sps => "SPS",
+            Family::Ignition => "Ignition",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Sku {
+    Consumer,
+    Corporate,
+    /// CSME firmware without AMT or the ME File System, for low end platforms
+    Slim,
+}
+
+impl Sku {
+    /// SKU type from the SKU attributes of a CSE manifest
+    pub fn from_attributes(attributes: u64) -> Option<Sku> {
+        match (attributes >> 4) & 0x7 {
+            0 => Some(Sku::Corporate),
+            1 => Some(Sku::Consumer),
+            2 => Some(Sku::Slim),
+            _ => None,
+        }
+    }
+}
+
+/// Evidence for the firmware SKU from a manifest
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SkuHint<'a> {
+    /// SKU type in the Client System Information extension of a CSE manifest
+    Attributes(Sku),
+    /// AMT module in the module headers of an ME 6 to 10 manifest, which only corporate firmware
+    /// includes
+    AmtModule(&'a str),
+}
+
+impl SkuHint<'_> {
+    /// SKU suggested by this hint
+    pub fn sku(&self) -> Sku {
+        match self {
+            SkuHint::Attributes(sku) => *sku,
+            SkuHint::AmtModule(_) => Sku::Corporate,
+        }
+    }
+}
+
+impl fmt::Display for SkuHint<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkuHint::Attributes(sku) => write!(f, "manifest SKU attributes {:?}", sku),
+            SkuHint::AmtModule(name) => write!(f, "AMT module {}", name),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{fmt, mem};
+use plain::Plain;
+
+/// Offset from the end of the BIOS region of the FIT pointer
+pub const POINTER_OFFSET: usize = 0x40;
+
+/// Address of the FIT header entry, holding `_FIT_   `
+pub const SIGNATURE: u64 = 0x2020_205F_5449_465F;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EntryKind {
+    Header,
+    Microcode,
+    StartupAcm,
+    DiagnosticAcm,
+    BiosStartupModule,
+    TpmPolicy,
+    BiosPolicy,
+    TxtPolicy,
+    KeyManifest,
+    BootPolicyManifest,
+    CseSecureBoot,
+    FeaturePolicy,
+    JmpDebugPolicy,
+    Unused,
+    Unknown(u8),
+}
+
+impl From<u8> for EntryKind {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => EntryKind::Header,
+            0x01 => EntryKind::Microcode,
+            0x02 => EntryKind::StartupAcm,
+            0x03 => EntryKind::DiagnosticAcm,
+            0x07 => EntryKind::BiosStartupModule,
+            0x08 => EntryKind::TpmPolicy,
+            0x09 => EntryKind::BiosPolicy,
+            0x0A => EntryKind::TxtPolicy,
+            0x0B => EntryKind::KeyManifest,
+            0x0C => EntryKind::BootPolicyManifest,
+            0x10 => EntryKind::CseSecureBoot,
+            0x2D => EntryKind::FeaturePolicy,
+            0x2F => EntryKind::JmpDebugPolicy,
+            0x7F => EntryKind::Unused,
+            unknown => EntryKind::Unknown(unknown),
+        }
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryKind::Header => write!(f, "FIT Header"),
+            EntryKind::Microcode => write!(f, "Microcode"),
+            EntryKind::StartupAcm => write!(f, "Startup ACM"),
+            EntryKind::DiagnosticAcm => write!(f, "Diagnostic ACM"),
+            EntryKind::BiosStartupModule => write!(f, "BIOS Startup Module"),
+            EntryKind::TpmPolicy => write!(f, "TPM Policy"),
+            EntryKind::BiosPolicy => write!(f, "BIOS Policy"),
+            EntryKind::TxtPolicy => write!(f, "TXT Policy"),
+            EntryKind::KeyManifest => write!(f, "Key Manifest"),
+            EntryKind::BootPolicyManifest => write!(f, "Boot Policy Manifest"),
+            EntryKind::CseSecureBoot => write!(f, "CSE Secure Boot"),
+            EntryKind::FeaturePolicy => write!(f, "Feature Policy"),
+            EntryKind::JmpDebugPolicy => write!(f, "JMP Debug Policy"),
+            EntryKind::Unused => write!(f, "Unused"),
+            EntryKind::Unknown(value) => write!(f, "Unknown {:#X}", value),
+        }
+    }
+}
+
+/// Firmware Interface Table entry
+#[repr(packed)]
+pub struct Entry {
+    pub address: u64,
+    pub size: [u8; 3],
+    pub reserved: u8,
+    pub version: u16,
+    /// Type in bits 6:0, checksum valid in bit 7
+    pub kind: u8,
+    pub checksum: u8,
+}
+
+impl Entry {
+    pub fn kind(&self) -> EntryKind {
+        EntryKind::from(self.kind & 0x7F)
+    }
+
+    /// True if the checksum field is valid
+    pub fn checksum_valid_bit(&self) -> bool {
+        self.kind & 0x80 != 0
+    }
+
+    /// Size field, in 16 byte units for the header and most other types
+    pub fn size(&self) -> u32 {
+        self.size[0] as u32 | (self.size[1] as u32) << 8 | (self.size[2] as u32) << 16
+    }
+}
+
+unsafe impl Plain for Entry {}
+
+/// FIT entry with its address translated to an offset in the BIOS region
+pub struct Item<'a> {
+    pub entry: &'a Entry,
+    pub offset: Option<usize>,
+}
+
+/// Firmware Interface Table of a BIOS region mapped just below 4 GiB
+pub struct Fit<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Fit<'a> {
+    /// Find the FIT through its pointer, returning `None` if there is no pointer
+    pub fn new(data: &'a [u8]) -> Result<Option<Fit<'a>>, String> {
+        if data.len() < POINTER_OFFSET {
+            return Ok(None);
+        }
+
+        let pointer = &data[data.len() - POINTER_OFFSET..][..8];
+        let mut address = [0; 8];
+        address.copy_from_slice(pointer);
+        let address = u64::from_le_bytes(address);
+        if address == 0 || address == u64::MAX {
+            return Ok(None);
+        }
+
+        let offset = translate(data.len(), address).ok_or_else(|| {
+            format!("FIT pointer {:#X} outside of BIOS region", address)
+        })?;
+        let fit = Fit { data, offset };
+
+        let header = fit.header()?;
+        let signature = header.address;
+        if signature != SIGNATURE {
+            return Err(format!("FIT header signature invalid: {:#X}", signature));
+        }
+
+        let end = offset + header.size() as usize * mem::size_of::<Entry>();
+        if end > data.len() {
+            return Err(format!("FIT entries truncated: {:#X} > {:#X}", end, data.len()));
+        }
+
+        Ok(Some(fit))
+    }
+
+    /// Offset of the table in the BIOS region
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn header(&self) -> Result<&'a Entry, String> {
+        plain::from_bytes(&self.data[self.offset..]).map_err(|err| {
+            format!("FIT header invalid: {:?}", err)
+        })
+    }
+
+    /// Every entry, including the header
+    pub fn entries(&self) -> &'a [Entry] {
+        let count = self.header().map_or(0, |header| header.size() as usize);
+        plain::slice_from_bytes_len(&self.data[self.offset..], count).unwrap()
+    }
+
+    /// Translate an address below 4 GiB to an offset in the BIOS region
+    pub fn translate(&self, address: u64) -> Option<usize> {
+        translate(self.data.len(), address)
+    }
+
+    /// Entries after the header, with their offsets in the BIOS region
+    pub fn items(&self) -> Vec<Item<'a>> {
+        self.entries().iter().skip(1).map(|entry| {
+            let address = entry.address;
+            Item {
+                entry,
+                offset: self.translate(address),
+            }
+        }).collect()
+    }
+
+    /// True if the table checksum is valid, or not used
+    pub fn checksum_valid(&self) -> bool {
+        match self.header() {
+            Ok(header) if header.checksum_valid_bit() => {
+                let len = mem::size_of_val(self.entries());
+                self.data[self.offset..self.offset + len].iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+            },
+            Ok(_) => true,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Translate an address below 4 GiB to an offset in a BIOS region of `len` bytes
+pub fn translate(len: usize, address: u64) -> Option<usize> {
+    let base = (1u64 << 32).checked_sub(len as u64)?;
+    if address >= base && address < (1u64 << 32) {
+        Some((address - base) as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    fn write_entry(data: &mut [u8], offset: usize, address: u64, size: u32, kind: u8) {
+        data[offset..offset + 8].copy_from_slice(&address.to_le_bytes());
+        data[offset + 8..offset + 12].copy_from_slice(&size.to_le_bytes());
+        data[offset + 12..offset + 14].copy_from_slice(&0x0100u16.to_le_bytes());
+        data[offset + 14] = kind;
+        data[offset + 15] = 0;
+    }
+
+    /// 4 KiB BIOS region at 0xFFFFF000 with a FIT at 0x800 listing a microcode update and an ACM
+    /// outside of the region
+    fn bios() -> Vec<u8> {
+        let mut data = vec![0xFF; 0x1000];
+        data[0xFC0..0xFC8].copy_from_slice(&0xFFFF_F800u64.to_le_bytes());
+        write_entry(&mut data, 0x800, SIGNATURE, 3, 0x80);
+        write_entry(&mut data, 0x810, 0xFFFF_F100, 0, 0x01);
+        write_entry(&mut data, 0x820, 0x1000, 0, 0x02);
+        data
+    }
+
+    #[test]
+    fn parse() {
+        let mut data = bios();
+        let fit = Fit::new(&data).unwrap().unwrap();
+        assert_eq!(fit.offset(), 0x800);
+        assert_eq!(fit.entries().len(), 3);
+        assert_eq!(fit.entries()[0].kind(), EntryKind::Header);
+
+        let items = fit.items();
+        let kinds: Vec<_> = items.iter().map(|item| (item.entry.kind(), item.offset)).collect();
+        assert_eq!(kinds, vec![
+            (EntryKind::Microcode, Some(0x100)),
+            (EntryKind::StartupAcm, None),
+        ]);
+        assert_eq!(fit.translate(0xFFFF_FFFF), Some(0xFFF));
+        assert_eq!(fit.translate(1 << 32), None);
+
+        // The checksum bit is set, so the entries must sum to zero
+        assert!(!fit.checksum_valid());
+        data[0x80F] = 0x77;
+        assert!(Fit::new(&data).unwrap().unwrap().checksum_valid());
+        // Without the checksum bit, the checksum is not used
+        data[0x80F] = 0x12;
+        data[0x80E] = 0x00;
+        assert!(Fit::new(&data).unwrap().unwrap().checksum_valid());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let mut data = bios();
+        assert!(Fit::new(&data[..0x20]).unwrap().is_none());
+
+        data[0xFC0..0xFC8].copy_from_slice(&[0xFF; 8]);
+        assert!(Fit::new(&data).unwrap().is_none());
+        data[0xFC0..0xFC8].copy_from_slice(&[0; 8]);
+        assert!(Fit::new(&data).unwrap().is_none());
+
+        data[0xFC0..0xFC8].copy_from_slice(&0xFFFF_E000u64.to_le_bytes());
+        assert_eq!(Fit::new(&data).err(), Some(String::from("FIT pointer 0xFFFFE000 outside of BIOS region")));
+
+        data[0xFC0..0xFC8].copy_from_slice(&0xFFFF_FFF8u64.to_le_bytes());
+        assert_eq!(Fit::new(&data).err(), Some(String::from("FIT header invalid: TooShort")));
+
+        data[0xFC0..0xFC8].copy_from_slice(&0xFFFF_F810u64.to_le_bytes());
+        assert_eq!(Fit::new(&data).err(), Some(String::from("FIT header signature invalid: 0xFFFFF100")));
+
+        data[0xFC0..0xFC8].copy_from_slice(&0xFFFF_F800u64.to_le_bytes());
+        data[0x808] = 0x81;
+        assert_eq!(Fit::new(&data).err(), Some(String::from("FIT entries truncated: 0x1010 > 0x1000")));
+    }
+}
@@ -1,7 +1,83 @@
 // SPDX-License-Identifier: MIT
 
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
 use plain::Plain;
 
+use super::RegionKind;
+
+/// Layout version of the flash descriptor
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Version {
+    /// ICH8 through 9 series PCH
+    V1,
+    /// 100 series PCH and later
+    V2,
+}
+
+impl Version {
+    /// Number of entries in the flash region table
+    pub fn regions(&self) -> usize {
+        match self {
+            Version::V1 => 5,
+            Version::V2 => 16,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpiFrequency {
+    Mhz17,
+    Mhz20,
+    Mhz30,
+    Mhz33,
+    Mhz48,
+    Mhz50,
+    Unknown(u8),
+}
+
+impl SpiFrequency {
+    pub fn new(value: u8, version: Version) -> Self {
+        match (value, version) {
+            (0, _) => SpiFrequency::Mhz20,
+            (1, _) => SpiFrequency::Mhz33,
+            (2, _) => SpiFrequency::Mhz48,
+            (4, Version::V1) => SpiFrequency::Mhz50,
+            (4, Version::V2) => SpiFrequency::Mhz30,
+            (6, _) => SpiFrequency::Mhz17,
+            (unknown, _) => SpiFrequency::Unknown(unknown),
+        }
+    }
+
+    /// Encoding of the frequency in the component table, if the version supports it
+    pub fn value(&self, version: Version) -> Option<u8> {
+        match (self, version) {
+            (SpiFrequency::Mhz20, _) => Some(0),
+            (SpiFrequency::Mhz33, _) => Some(1),
+            (SpiFrequency::Mhz48, _) => Some(2),
+            (SpiFrequency::Mhz50, Version::V1) => Some(4),
+            (SpiFrequency::Mhz30, Version::V2) => Some(4),
+            (SpiFrequency::Mhz17, _) => Some(6),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SpiFrequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpiFrequency::Mhz17 => write!(f, "17 MHz"),
+            SpiFrequency::Mhz20 => write!(f, "20 MHz"),
+            SpiFrequency::Mhz30 => write!(f, "30 MHz"),
+            SpiFrequency::Mhz33 => write!(f, "33 MHz"),
+            SpiFrequency::Mhz48 => write!(f, "48 MHz"),
+            SpiFrequency::Mhz50 => write!(f, "50 MHz"),
+            SpiFrequency::Unknown(value) => write!(f, "Unknown ({})", value),
+        }
+    }
+}
+
 #[repr(packed)]
 pub struct Descriptor {
     pub valsig: u32,
@@ -12,11 +88,95 @@ pub struct Descriptor {
     pub umap1: u32,
 }
 
+impl Descriptor {
+    /// Offset of the flash component table
+    pub fn fcba(&self) -> usize {
+        ((self.map0 & 0xff) << 4) as usize
+    }
+
+    /// Offset of the flash region table
+    pub fn frba(&self) -> usize {
+        (((self.map0 >> 16) & 0xff) << 4) as usize
+    }
+
+    /// Offset of the flash master table
+    pub fn fmba(&self) -> usize {
+        ((self.map1 & 0xff) << 4) as usize
+    }
+
+    /// Offset of the PCH strap table
+    pub fn fpsba(&self) -> usize {
+        (((self.map1 >> 16) & 0xff) << 4) as usize
+    }
+
+    /// Offset of the processor strap table
+    pub fn fmsba(&self) -> usize {
+        ((self.map2 & 0xff) << 4) as usize
+    }
+
+    /// Offset of the VSCC table, from the upper map
+    pub fn vtba(&self) -> usize {
+        ((self.umap1 & 0xff) << 4) as usize
+    }
+
+    /// Length of the VSCC table in dwords, from the upper map
+    pub fn vtl(&self) -> usize {
+        ((self.umap1 >> 8) & 0xff) as usize
+    }
+}
+
 unsafe impl Plain for Descriptor {}
 
 #[repr(packed)]
 pub struct Region {
-    pub data: [u32; 9],
+    pub data: [u32; 16],
+}
+
+impl Region {
+    /// Base and limit of a region, or None if it is unused
+    pub fn base_limit(&self, index: usize, version: Version) -> Option<(usize, usize)> {
+        if index >= version.regions() {
+            return None;
+        }
+
+        let reg = self.data[index];
+
+        // All versions use 15-bit fields, like ifdtool
+        let base_mask = 0x7fff;
+        let limit_mask = base_mask << 16;
+
+        let base = (reg & base_mask) << 12;
+        let limit = ((reg & limit_mask) >> 4) | 0xfff;
+
+        if limit > base {
+            Some((base as usize, limit as usize))
+        } else {
+            None
+        }
+    }
+
+    /// Set the base and limit of a region, or mark it unused with None
+    pub fn set_base_limit(&mut self, index: usize, base_limit: Option<(usize, usize)>, version: Version) -> Result<(), String> {
+        if index >= version.regions() {
+            return Err(format!("Flash region {} not supported by {:?} descriptor", index, version));
+        }
+
+        let mask = 0x7fff;
+
+        self.data[index] = match base_limit {
+            Some((base, limit)) => {
+                let base_block = base >> 12;
+                let limit_block = limit >> 12;
+                if base & 0xfff != 0 || limit & 0xfff != 0xfff || limit_block > mask || base_block > limit_block {
+                    return Err(format!("Flash region {} invalid: {:#X} - {:#X}", index, base, limit));
+                }
+                (base_block | (limit_block << 16)) as u32
+            },
+            None => mask as u32,
+        };
+
+        Ok(())
+    }
 }
 
 unsafe impl Plain for Region {}
@@ -28,6 +188,50 @@ pub struct Component {
     pub pb: u32,
 }
 
+impl Component {
+    /// Size in bytes of the given chip (0 or 1), or None if it is unused
+    pub fn density(&self, chip: usize, version: Version) -> Option<usize> {
+        let (bits, mask) = match version {
+            Version::V1 => (3, 0x7),
+            Version::V2 => (4, 0xf),
+        };
+
+        match (self.comp >> (chip * bits)) & mask {
+            value @ 0..=7 => Some(0x80000 << value),
+            _ => None,
+        }
+    }
+
+    pub fn read_clock(&self, version: Version) -> SpiFrequency {
+        SpiFrequency::new(((self.comp >> 17) & 0x7) as u8, version)
+    }
+
+    pub fn fast_read(&self) -> bool {
+        self.comp & (1 << 20) != 0
+    }
+
+    pub fn fast_read_clock(&self, version: Version) -> SpiFrequency {
+        SpiFrequency::new(((self.comp >> 21) & 0x7) as u8, version)
+    }
+
+    pub fn erase_clock(&self, version: Version) -> SpiFrequency {
+        SpiFrequency::new(((self.comp >> 24) & 0x7) as u8, version)
+    }
+
+    pub fn read_id_clock(&self, version: Version) -> SpiFrequency {
+        SpiFrequency::new(((self.comp >> 27) & 0x7) as u8, version)
+    }
+
+    pub fn dual_output_fast_read(&self) -> bool {
+        self.comp & (1 << 30) != 0
+    }
+
+    /// Opcodes the SPI controller will refuse to send, zero entries are unused
+    pub fn invalid_instructions(&self) -> Vec<u8> {
+        self.ill.to_le_bytes().iter().cloned().filter(|&op| op != 0).collect()
+    }
+}
+
 unsafe impl Plain for Component {}
 
 #[repr(packed)]
@@ -46,11 +250,344 @@ pub struct Master {
     pub mstr5: u32,
 }
 
+impl Master {
+    pub fn value(&self, kind: MasterKind) -> u32 {
+        match kind {
+            MasterKind::Host => self.mstr1,
+            MasterKind::ManagementEngine => self.mstr2,
+            MasterKind::Ethernet => self.mstr3,
+            MasterKind::Reserved => self.mstr4,
+            MasterKind::EmbeddedController => self.mstr5,
+        }
+    }
+
+    pub fn set_value(&mut self, kind: MasterKind, value: u32) {
+        match kind {
+            MasterKind::Host => self.mstr1 = value,
+            MasterKind::ManagementEngine => self.mstr2 = value,
+            MasterKind::Ethernet => self.mstr3 = value,
+            MasterKind::Reserved => self.mstr4 = value,
+            MasterKind::EmbeddedController => self.mstr5 = value,
+        }
+    }
+
+    /// Decode the read and write permissions of every master
+    pub fn access(&self, version: Version) -> Vec<MasterAccess> {
+        MasterKind::all(version).iter().map(|&kind| {
+            MasterAccess::new(kind, self.value(kind), version)
+        }).collect()
+    }
+}
+
 unsafe impl Plain for Master {}
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MasterKind {
+    Host,
+    ManagementEngine,
+    Ethernet,
+    Reserved,
+    EmbeddedController,
+}
+
+impl MasterKind {
+    /// Masters present in a descriptor version
+    pub fn all(version: Version) -> &'static [MasterKind] {
+        const ALL: [MasterKind; 5] = [
+            MasterKind::Host,
+            MasterKind::ManagementEngine,
+            MasterKind::Ethernet,
+            MasterKind::Reserved,
+            MasterKind::EmbeddedController,
+        ];
+
+        match version {
+            Version::V1 => &ALL[..3],
+            Version::V2 => &ALL,
+        }
+    }
+}
+
+impl fmt::Display for MasterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MasterKind::Host => "Host CPU/BIOS",
+            MasterKind::ManagementEngine => "Intel ME",
+            MasterKind::Ethernet => "GbE",
+            MasterKind::Reserved => "Reserved",
+            MasterKind::EmbeddedController => "EC",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MasterAccess {
+    pub kind: MasterKind,
+    /// Regions the master may read, one bit per `RegionKind`
+    pub read: u16,
+    /// Regions the master may write, one bit per `RegionKind`
+    pub write: u16,
+}
+
+impl MasterAccess {
+    pub fn new(kind: MasterKind, value: u32, version: Version) -> Self {
+        let (read, write) = match version {
+            Version::V1 => (
+                (value >> 16) & 0xff,
+                (value >> 24) & 0xff,
+            ),
+            Version::V2 => (
+                ((value >> 8) & 0xfff) | (((value >> 4) & 0xf) << 12),
+                ((value >> 20) & 0xfff) | ((value & 0xf) << 12),
+            ),
+        };
+
+        MasterAccess {
+            kind,
+            read: read as u16,
+            write: write as u16,
+        }
+    }
+
+    /// Encode the permissions into a master value, keeping its other bits
+    pub fn value(&self, old: u32, version: Version) -> u32 {
+        let read = self.read as u32;
+        let write = self.write as u32;
+        match version {
+            Version::V1 => {
+                (old & 0xffff) | ((read & 0xff) << 16) | ((write & 0xff) << 24)
+            },
+            Version::V2 => {
+                ((read & 0xfff) << 8) | ((write & 0xfff) << 20) |
+                (((read >> 12) & 0xf) << 4) | ((write >> 12) & 0xf)
+            },
+        }
+    }
+
+    pub fn set_read(&mut self, region: RegionKind, allow: bool) {
+        if allow {
+            self.read |= 1 << region as usize;
+        } else {
+            self.read &= !(1 << region as usize);
+        }
+    }
+
+    pub fn set_write(&mut self, region: RegionKind, allow: bool) {
+        if allow {
+            self.write |= 1 << region as usize;
+        } else {
+            self.write &= !(1 << region as usize);
+        }
+    }
+
+    pub fn can_read(&self, region: RegionKind) -> bool {
+        self.read & (1 << region as usize) != 0
+    }
+
+    pub fn can_write(&self, region: RegionKind) -> bool {
+        self.write & (1 << region as usize) != 0
+    }
+
+    /// Permissions that leave the descriptor or ME open to modification
+    pub fn issues(&self) -> Vec<AccessIssue> {
+        let mut issues = Vec::new();
+
+        if self.can_write(RegionKind::Descriptor) {
+            issues.push(AccessIssue::DescriptorWritable(self.kind));
+        }
+
+        if self.kind != MasterKind::ManagementEngine && self.can_write(RegionKind::ManagementEngine) {
+            issues.push(AccessIssue::ManagementEngineWritable(self.kind));
+        }
+
+        issues
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AccessIssue {
+    DescriptorWritable(MasterKind),
+    ManagementEngineWritable(MasterKind),
+}
+
+impl fmt::Display for AccessIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessIssue::DescriptorWritable(kind) => {
+                write!(f, "{} can write the flash descriptor", kind)
+            },
+            AccessIssue::ManagementEngineWritable(kind) => {
+                write!(f, "{} can write the Intel ME region", kind)
+            },
+        }
+    }
+}
+
 #[repr(packed)]
 pub struct ProcStrap {
     pub data: [u32; 8]
 }
 
 unsafe impl Plain for ProcStrap {}
+
+/// Vendor specific component capabilities of a SPI part
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ComponentCapabilities {
+    /// Size in bytes erased by `erase_opcode`
+    pub block_erase_size: usize,
+    /// Write granularity in bytes, 1 or 64
+    pub write_granularity: usize,
+    pub write_status_required: bool,
+    pub write_enable_on_write_status: bool,
+    pub erase_opcode: u8,
+}
+
+impl ComponentCapabilities {
+    pub fn new(value: u16) -> Self {
+        ComponentCapabilities {
+            block_erase_size: match value & 0x3 {
+                0 => 256,
+                1 => 4096,
+                2 => 8192,
+                _ => 65536,
+            },
+            write_granularity: if value & (1 << 2) != 0 { 64 } else { 1 },
+            write_status_required: value & (1 << 3) != 0,
+            write_enable_on_write_status: value & (1 << 4) != 0,
+            erase_opcode: (value >> 8) as u8,
+        }
+    }
+}
+
+/// VSCC table entry, matching a SPI part by JEDEC ID
+#[repr(packed)]
+pub struct VsccEntry {
+    pub jid: u32,
+    pub vscc: u32,
+}
+
+impl VsccEntry {
+    pub fn vendor_id(&self) -> u8 {
+        self.jid as u8
+    }
+
+    pub fn device_id(&self) -> u16 {
+        (((self.jid >> 8) & 0xff) << 8 | ((self.jid >> 16) & 0xff)) as u16
+    }
+
+    /// Capabilities used for the upper flash partition
+    pub fn upper(&self) -> ComponentCapabilities {
+        ComponentCapabilities::new((self.vscc >> 16) as u16)
+    }
+
+    /// Capabilities used for the lower flash partition
+    pub fn lower(&self) -> ComponentCapabilities {
+        ComponentCapabilities::new(self.vscc as u16)
+    }
+}
+
+unsafe impl Plain for VsccEntry {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn component() {
+        // Two chips of 8 MiB and 4 MiB, 17 MHz read clock, 50 MHz fast read
+        let component = Component {
+            comp: 0x4 | 0x3 << 3 | 0x6 << 17 | 1 << 20 | 0x4 << 21 | 0x1 << 24 | 0x6 << 27,
+            ill: 0x0000_C720,
+            pb: 0,
+        };
+        assert_eq!(component.density(0, Version::V1), Some(0x80_0000));
+        assert_eq!(component.density(1, Version::V1), Some(0x40_0000));
+        assert_eq!(component.read_clock(Version::V1), SpiFrequency::Mhz17);
+        assert!(component.fast_read());
+        assert_eq!(component.fast_read_clock(Version::V1), SpiFrequency::Mhz50);
+        assert_eq!(component.fast_read_clock(Version::V2), SpiFrequency::Mhz30);
+        assert_eq!(component.erase_clock(Version::V1), SpiFrequency::Mhz33);
+        assert_eq!(component.read_id_clock(Version::V1), SpiFrequency::Mhz17);
+        assert!(! component.dual_output_fast_read());
+        assert_eq!(component.invalid_instructions(), vec![0x20, 0xC7]);
+    }
+
+    #[test]
+    fn component_v2_density() {
+        // 16 MiB chip 0, chip 1 unused
+        let component = Component { comp: 0xF5, ill: 0, pb: 0 };
+        assert_eq!(component.density(0, Version::V2), Some(0x100_0000));
+        assert_eq!(component.density(1, Version::V2), None);
+        assert_eq!(SpiFrequency::new(3, Version::V2), SpiFrequency::Unknown(3));
+        assert_eq!(SpiFrequency::Mhz50.value(Version::V2), None);
+        assert_eq!(SpiFrequency::Mhz30.value(Version::V2), Some(4));
+    }
+
+    #[test]
+    fn master_access_v1() {
+        // Host reads the descriptor, BIOS and GbE, and writes BIOS and GbE
+        let access = MasterAccess::new(MasterKind::Host, 0x0A0B_0000, Version::V1);
+        assert_eq!((access.read, access.write), (0x0B, 0x0A));
+        assert!(access.can_read(RegionKind::Descriptor));
+        assert!(! access.can_write(RegionKind::Descriptor));
+        assert!(access.issues().is_empty());
+        // Requester ID in the low bits is kept
+        assert_eq!(access.value(0xFFFF_0000 | 0x1234, Version::V1), 0x0A0B_1234);
+
+        let access = MasterAccess::new(MasterKind::Host, 0xFFFF_0000, Version::V1);
+        assert_eq!(access.issues(), vec![
+            AccessIssue::DescriptorWritable(MasterKind::Host),
+            AccessIssue::ManagementEngineWritable(MasterKind::Host),
+        ]);
+
+        // The ME may write its own region
+        let access = MasterAccess::new(MasterKind::ManagementEngine, 0x0C0D_0000, Version::V1);
+        assert!(access.issues().is_empty());
+    }
+
+    #[test]
+    fn master_access_v2() {
+        // Regions 0-11 in bits 19:8 and 31:20, regions 12-15 in bits 7:4 and 3:0
+        let access = MasterAccess::new(MasterKind::EmbeddedController, 0x1012_3456, Version::V2);
+        assert_eq!(access.read, 0x5234);
+        assert_eq!(access.write, 0x6101);
+        assert!(! access.can_read(RegionKind::Ptt));
+        assert!(access.can_read(RegionKind::Reserved14));
+        assert_eq!(access.value(0, Version::V2), 0x1012_3456);
+        assert_eq!(access.issues(), vec![AccessIssue::DescriptorWritable(MasterKind::EmbeddedController)]);
+
+        let mut access = access;
+        access.set_write(RegionKind::Descriptor, false);
+        access.set_read(RegionKind::Ptt, true);
+        assert_eq!(access.value(0, Version::V2), 0x1002_34D6);
+        assert!(access.issues().is_empty());
+
+        assert_eq!(MasterKind::all(Version::V1).len(), 3);
+        assert_eq!(MasterKind::all(Version::V2).len(), 5);
+    }
+
+    #[test]
+    fn vscc_entry() {
+        // Winbond W25Q64, JEDEC ID EF 40 17
+        let entry = VsccEntry { jid: 0x0017_40EF, vscc: 0x2005_D813 };
+        assert_eq!(entry.vendor_id(), 0xEF);
+        assert_eq!(entry.device_id(), 0x4017);
+        assert_eq!(entry.upper(), ComponentCapabilities {
+            block_erase_size: 4096,
+            write_granularity: 64,
+            write_status_required: false,
+            write_enable_on_write_status: false,
+            erase_opcode: 0x20,
+        });
+        assert_eq!(entry.lower(), ComponentCapabilities {
+            block_erase_size: 65536,
+            write_granularity: 1,
+            write_status_required: false,
+            write_enable_on_write_status: true,
+            erase_opcode: 0xD8,
+        });
+        assert_eq!(ComponentCapabilities::new(0x0008).block_erase_size, 256);
+        assert!(ComponentCapabilities::new(0x0008).write_status_required);
+    }
+}
@@ -0,0 +1,324 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::string::String;
+use core::{fmt, mem, str};
+use plain::Plain;
+
+use super::crc;
+
+/// Intel ME Flash Partition Table header
+#[repr(packed)]
+pub struct Header {
+    pub signature: [u8; 4],
+    pub num_entries: u32,
+    pub header_version: u8,
+    pub entry_version: u8,
+    pub header_length: u8,
+    /// Byte checksum before version 2.1, flags afterwards
+    pub checksum: u8,
+    pub flash_cycle_lifetime: u16,
+    pub flash_cycle_limit: u16,
+    pub uma_size: u32,
+    /// Flags before version 2.1, CRC32 of the header afterwards
+    pub flags: u32,
+    pub fitc_major: u16,
+    pub fitc_minor: u16,
+    pub fitc_hotfix: u16,
+    pub fitc_build: u16,
+}
+
+impl Header {
+    pub fn valid(&self) -> bool {
+        self.signature == *b"$FPT"
+    }
+
+    /// True if the header is protected by a CRC32 instead of a byte checksum
+    pub fn crc32(&self) -> bool {
+        self.header_version >= 0x21
+    }
+
+    /// Version of the FITC tool that built the image
+    pub fn fitc_version(&self) -> String {
+        let (major, minor, hotfix, build) = (self.fitc_major, self.fitc_minor, self.fitc_hotfix, self.fitc_build);
+        format!("{}.{}.{}.{}", major, minor, hotfix, build)
+    }
+}
+
+unsafe impl Plain for Header {}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PartitionKind {
+    Code,
+    Data,
+    Nvram,
+    Generic,
+    Effs,
+    Rom,
+    Unknown(u8),
+}
+
+impl From<u8> for PartitionKind {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => PartitionKind::Code,
+            1 => PartitionKind::Data,
+            2 => PartitionKind::Nvram,
+            3 => PartitionKind::Generic,
+            4 => PartitionKind::Effs,
+            5 => PartitionKind::Rom,
+            unknown => PartitionKind::Unknown(unknown),
+        }
+    }
+}
+
+/// Intel ME Flash Partition Table entry
+#[repr(packed)]
+pub struct Entry {
+    pub name: [u8; 4],
+    pub owner: [u8; 4],
+    pub offset: u32,
+    pub length: u32,
+    pub start_tokens: u32,
+    pub max_tokens: u32,
+    pub scratch_sectors: u32,
+    pub flags: u32,
+}
+
+impl Entry {
+    /// Partition name, such as `FTPR`
+    pub fn name(&self) -> &str {
+        str::from_utf8(&self.name).unwrap_or("").trim_end_matches('\0')
+    }
+
+    pub fn kind(&self) -> PartitionKind {
+        PartitionKind::from((self.flags & 0x7F) as u8)
+    }
+
+    /// False if the entry is marked invalid in its flags
+    pub fn valid(&self) -> bool {
+        (self.flags >> 24) != 0xFF
+    }
+
+    /// True if the partition has no content in flash
+    pub fn disabled(&self) -> bool {
+        self.offset == 0xFFFF_FFFF || self.length == 0
+    }
+}
+
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (offset, length, flags) = (self.offset, self.length, self.flags);
+        f.debug_struct("Entry")
+            .field("name", &self.name())
+            .field("offset", &offset)
+            .field("length", &length)
+            .field("flags", &flags)
+            .finish()
+    }
+}
+
+unsafe impl Plain for Entry {}
+
+/// Flash Partition Table located in an ME region
+pub struct Fpt<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Fpt<'a> {
+    /// Find the partition table at the start of an ME region
+    pub fn new(data: &'a [u8]) -> Result<Fpt<'a>, String> {
+        for &offset in &[0x10, 0] {
+            if data.len() >= offset + mem::size_of::<Header>() && data[offset..offset + 4] == *b"$FPT" {
+                let fpt = Fpt { data, offset };
+                let end = fpt.entries_offset() + fpt.header().num_entries as usize * mem::size_of::<Entry>();
+                if end > data.len() {
+                    return Err(format!("$FPT entries truncated: {:#X} > {:#X}", end, data.len()));
+                }
+                return Ok(fpt);
+            }
+        }
+
+        Err(String::from("$FPT not found"))
+    }
+
+    /// The ME region containing the partition table
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Offset of the `$FPT` signature in the ME region
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn header(&self) -> &'a Header {
+        plain::from_bytes(&self.data[self.offset..]).unwrap()
+    }
+
+    /// Offset of the first entry in the ME region
+    pub fn entries_offset(&self) -> usize {
+        self.offset + (self.header().header_length as usize).max(mem::size_of::<Header>())
+    }
+
+    pub fn entries(&self) -> &'a [Entry] {
+        let offset = self.entries_offset();
+        let count = self.header().num_entries as usize;
+        plain::slice_from_bytes_len(&self.data[offset..], count).unwrap()
+    }
+
+    /// Find a partition entry by name
+    pub fn entry(&self, name: &str) -> Option<&'a Entry> {
+        self.entries().iter().find(|entry| entry.name() == name)
+    }
+
+    /// Contents of a partition, relative to the ME region
+    pub fn partition(&self, entry: &Entry) -> Option<&'a [u8]> {
+        if entry.disabled() {
+            return None;
+        }
+        let start = entry.offset as usize;
+        let end = start.checked_add(entry.length as usize)?;
+        self.data.get(start..end)
+    }
+
+    /// Bytes covered by the header checksum
+    pub fn checksum_data(&self) -> &'a [u8] {
+        let header = self.header();
+        if header.crc32() {
+            &self.data[self.offset..self.offset + (header.header_length as usize).max(mem::size_of::<Header>())]
+        } else if self.offset >= 0x10 {
+            &self.data[self.offset - 0x10..self.offset + 0x20]
+        } else {
+            &self.data[self.offset..self.offset + mem::size_of::<Header>()]
+        }
+    }
+
+    /// Expected header checksum, computed from the header contents
+    pub fn checksum(&self) -> u32 {
+        let header = self.header();
+        if header.crc32() {
+            let mut bytes = self.checksum_data().to_vec();
+            // CRC32 field is zeroed while computing the checksum
+            for b in &mut bytes[0x14..0x18] {
+                *b = 0;
+            }
+            crc::crc32(&bytes)
+        } else {
+            let sum = self.checksum_data().iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+            sum.wrapping_sub(header.checksum).wrapping_neg() as u32
+        }
+    }
+
+    pub fn checksum_valid(&self) -> bool {
+        let header = self.header();
+        if header.crc32() {
+            self.checksum() == header.flags
+        } else {
+            self.checksum() == header.checksum as u32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn entry(data: &mut [u8], offset: usize, name: &[u8; 4], start: u32, length: u32, flags: u32) {
+        data[offset..offset + 4].copy_from_slice(name);
+        write_u32(data, offset + 0x08, start);
+        write_u32(data, offset + 0x0C, length);
+        write_u32(data, offset + 0x1C, flags);
+    }
+
+    /// Version 2.0 table after the 16 byte ROM bypass vector, with a byte checksum
+    fn fpt_v20() -> Vec<u8> {
+        let mut data = vec![0; 0x100];
+        data[0x10..0x14].copy_from_slice(b"$FPT");
+        write_u32(&mut data, 0x14, 2);
+        data[0x18..0x1B].copy_from_slice(&[0x20, 0x10, 0x20]);
+        write_u32(&mut data, 0x20, 0x1234);
+        data[0x28..0x30].copy_from_slice(&[11, 0, 8, 0, 70, 0, 0xB8, 0x0B]);
+        entry(&mut data, 0x30, b"FTPR", 0x80, 0x40, 0);
+        entry(&mut data, 0x50, b"NFTP", 0xC0, 0x80, 0xFF00_0000);
+        data
+    }
+
+    #[test]
+    fn parse() {
+        let data = fpt_v20();
+        let fpt = Fpt::new(&data).unwrap();
+        assert_eq!(fpt.offset(), 0x10);
+        assert_eq!(fpt.entries_offset(), 0x30);
+
+        let header = fpt.header();
+        assert!(header.valid());
+        assert!(!header.crc32());
+        assert_eq!(header.fitc_version(), "11.8.70.3000");
+
+        let entries = fpt.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name(), "FTPR");
+        assert_eq!(entries[0].kind(), PartitionKind::Code);
+        assert!(entries[0].valid());
+        assert_eq!(fpt.partition(&entries[0]), Some(&data[0x80..0xC0]));
+        assert!(!entries[1].valid());
+        // Extends past the end of the region
+        assert_eq!(fpt.partition(&entries[1]), None);
+        assert!(fpt.entry("MFS").is_none());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let mut data = fpt_v20();
+        assert_eq!(Fpt::new(&data[..0x28]).err(), Some(String::from("$FPT not found")));
+
+        write_u32(&mut data, 0x14, 7);
+        assert_eq!(Fpt::new(&data).err(), Some(String::from("$FPT entries truncated: 0x110 > 0x100")));
+
+        data[0x10] = 0;
+        assert_eq!(Fpt::new(&data).err(), Some(String::from("$FPT not found")));
+    }
+
+    #[test]
+    fn checksum_bytes() {
+        // Sum of the bypass vector and header bytes must be zero
+        let mut data = fpt_v20();
+        let fpt = Fpt::new(&data).unwrap();
+        assert_eq!(fpt.checksum_data(), &data[..0x30]);
+        assert_eq!(fpt.checksum(), 0x3E);
+        assert!(!fpt.checksum_valid());
+
+        data[0x1B] = 0x3E;
+        let fpt = Fpt::new(&data).unwrap();
+        assert_eq!(fpt.checksum(), 0x3E);
+        assert!(fpt.checksum_valid());
+        assert_eq!(data[..0x30].iter().fold(0u8, |sum, &b| sum.wrapping_add(b)), 0);
+    }
+
+    #[test]
+    fn checksum_crc32() {
+        // Version 2.1 table at offset 0, CRC32 of the header with the CRC field zeroed
+        let mut data = vec![0; 0x40];
+        data[0x00..0x04].copy_from_slice(b"$FPT");
+        write_u32(&mut data, 0x04, 1);
+        data[0x08..0x0B].copy_from_slice(&[0x21, 0x10, 0x20]);
+        data[0x18..0x20].copy_from_slice(&[12, 0, 0, 0, 1, 0, 0xE8, 0x03]);
+        let fpt = Fpt::new(&data).unwrap();
+        assert!(fpt.header().crc32());
+        assert_eq!(fpt.checksum(), 0xA356_EB82);
+        assert!(!fpt.checksum_valid());
+
+        data[0x14..0x18].copy_from_slice(&[0x82, 0xEB, 0x56, 0xA3]);
+        let fpt = Fpt::new(&data).unwrap();
+        assert_eq!(fpt.checksum(), 0xA356_EB82);
+        assert!(fpt.checksum_valid());
+    }
+}
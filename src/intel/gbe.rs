@@ -0,0 +1,81 @@
+// SPDX-License-Identifier: MIT
+
+use plain::Plain;
+
+/// Value that the first 0x40 NVM words must sum to
+pub const CHECKSUM: u16 = 0xBABA;
+
+/// Size of each of the two NVM banks in the GbE region
+pub const BANK_SIZE: usize = 0x1000;
+
+/// PHY register write from the extended configuration area
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PhyConfig {
+    pub address: u16,
+    pub data: u16,
+}
+
+/// Start of an Intel GbE NVM bank
+#[repr(packed)]
+pub struct Nvm {
+    pub words: [u16; 0x40],
+}
+
+impl Nvm {
+    /// True if word 0x13 holds the valid bank signature
+    pub fn valid(&self) -> bool {
+        (self.words[0x13] >> 14) == 0b10
+    }
+
+    pub fn mac_address(&self) -> [u8; 6] {
+        let mut mac = [0; 6];
+        for i in 0..3 {
+            let word = self.words[i];
+            mac[i * 2] = word as u8;
+            mac[i * 2 + 1] = (word >> 8) as u8;
+        }
+        mac
+    }
+
+    /// Set the MAC address, without updating the checksum
+    pub fn set_mac_address(&mut self, mac: [u8; 6]) {
+        for i in 0..3 {
+            self.words[i] = mac[i * 2] as u16 | (mac[i * 2 + 1] as u16) << 8;
+        }
+    }
+
+    /// Image version as major, minor and build
+    pub fn version(&self) -> (u8, u8, u8) {
+        let word = self.words[0x05];
+        ((word >> 12) as u8, ((word >> 4) & 0xff) as u8, (word & 0xf) as u8)
+    }
+
+    /// Offset and length in dwords of the PHY extended configuration area
+    pub fn phy_config_area(&self) -> (usize, usize) {
+        let pointer = (self.words[0x14] & 0xfff) as usize;
+        let length = (self.words[0x15] >> 8) as usize;
+        (pointer, length)
+    }
+
+    pub fn checksum(&self) -> u16 {
+        self.words[0x3F]
+    }
+
+    /// Sum of the checksummed words, which must equal `CHECKSUM`
+    pub fn sum(&self) -> u16 {
+        let words = self.words;
+        words.iter().fold(0u16, |sum, &word| sum.wrapping_add(word))
+    }
+
+    pub fn checksum_valid(&self) -> bool {
+        self.sum() == CHECKSUM
+    }
+
+    /// Recompute word 0x3F so that the checksum is valid
+    pub fn fix_checksum(&mut self) {
+        let sum = self.sum().wrapping_sub(self.words[0x3F]);
+        self.words[0x3F] = CHECKSUM.wrapping_sub(sum);
+    }
+}
+
+unsafe impl Plain for Nvm {}
@@ -0,0 +1,417 @@
+// SPDX-License-Identifier: MIT
+
+//! LZMA decoder for compressed ME modules, following the LZMA SDK specification
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Size of the `.lzma` header: properties, dictionary size and uncompressed size
+pub const HEADER_SIZE: usize = 13;
+
+/// Properties and dictionary size of CSME modules that pad the header with 3 zero bytes
+const PADDED_HEADER: [u8; 5] = [0x36, 0x00, 0x40, 0x00, 0x00];
+
+const PROB_INIT: u16 = 1 << 10;
+const STATES: usize = 12;
+const POS_STATES_MAX: usize = 1 << 4;
+const END_POS_MODEL_INDEX: u32 = 14;
+const FULL_DISTANCES: usize = 1 << (END_POS_MODEL_INDEX >> 1);
+const ALIGN_BITS: usize = 4;
+const LEN_TO_POS_STATES: usize = 4;
+const MATCH_MIN_LEN: usize = 2;
+
+struct RangeDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(data: &'a [u8]) -> Result<RangeDecoder<'a>, String> {
+        if data.len() < 5 || data[0] != 0 {
+            return Err(String::from("LZMA stream invalid"));
+        }
+        let code = u32::from_be_bytes([data[1], data[2], data[3], data[4]]);
+        if code == 0xFFFF_FFFF {
+            return Err(String::from("LZMA stream invalid"));
+        }
+        Ok(RangeDecoder {
+            data,
+            pos: 5,
+            range: 0xFFFF_FFFF,
+            code,
+        })
+    }
+
+    fn next_byte(&mut self) -> Result<u8, String> {
+        let byte = *self.data.get(self.pos).ok_or_else(|| String::from("LZMA stream truncated"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn normalize(&mut self) -> Result<(), String> {
+        if self.range < (1 << 24) {
+            self.range <<= 8;
+            self.code = (self.code << 8) | self.next_byte()? as u32;
+        }
+        Ok(())
+    }
+
+    fn direct_bits(&mut self, bits: u32) -> Result<u32, String> {
+        let mut result = 0u32;
+        for _ in 0..bits {
+            self.range >>= 1;
+            self.code = self.code.wrapping_sub(self.range);
+            let t = 0u32.wrapping_sub(self.code >> 31);
+            self.code = self.code.wrapping_add(self.range & t);
+            if self.code == self.range {
+                return Err(String::from("LZMA stream corrupted"));
+            }
+            self.normalize()?;
+            result = (result << 1).wrapping_add(t.wrapping_add(1));
+        }
+        Ok(result)
+    }
+
+    fn bit(&mut self, prob: &mut u16) -> Result<u32, String> {
+        let bound = (self.range >> 11) * (*prob as u32);
+        let bit = if self.code < bound {
+            *prob += (2048 - *prob) >> 5;
+            self.range = bound;
+            0
+        } else {
+            *prob -= *prob >> 5;
+            self.code -= bound;
+            self.range -= bound;
+            1
+        };
+        self.normalize()?;
+        Ok(bit)
+    }
+
+    fn bit_tree(&mut self, probs: &mut [u16], bits: u32) -> Result<u32, String> {
+        let mut m = 1;
+        for _ in 0..bits {
+            m = (m << 1) + self.bit(&mut probs[m as usize])?;
+        }
+        Ok(m - (1 << bits))
+    }
+
+    fn bit_tree_reverse(&mut self, probs: &mut [u16], bits: u32) -> Result<u32, String> {
+        let mut m = 1;
+        let mut symbol = 0;
+        for i in 0..bits {
+            let bit = self.bit(&mut probs[m as usize])?;
+            m = (m << 1) + bit;
+            symbol |= bit << i;
+        }
+        Ok(symbol)
+    }
+}
+
+struct LenDecoder {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 1 << 3]; POS_STATES_MAX],
+    mid: [[u16; 1 << 3]; POS_STATES_MAX],
+    high: [u16; 1 << 8],
+}
+
+impl LenDecoder {
+    fn new() -> LenDecoder {
+        LenDecoder {
+            choice: PROB_INIT,
+            choice2: PROB_INIT,
+            low: [[PROB_INIT; 1 << 3]; POS_STATES_MAX],
+            mid: [[PROB_INIT; 1 << 3]; POS_STATES_MAX],
+            high: [PROB_INIT; 1 << 8],
+        }
+    }
+
+    fn decode(&mut self, rc: &mut RangeDecoder, pos_state: usize) -> Result<usize, String> {
+        if rc.bit(&mut self.choice)? == 0 {
+            return Ok(rc.bit_tree(&mut self.low[pos_state], 3)? as usize);
+        }
+        if rc.bit(&mut self.choice2)? == 0 {
+            return Ok(8 + rc.bit_tree(&mut self.mid[pos_state], 3)? as usize);
+        }
+        Ok(16 + rc.bit_tree(&mut self.high, 8)? as usize)
+    }
+}
+
+/// Decompress an `.lzma` stream with a 13 byte header
+///
+/// If the header does not give the uncompressed size, `size` is used if known, otherwise the
+/// stream must end with an end marker. CSME modules with the header `36 00 40 00 00` may
+/// have 3 zero bytes after the header, which are skipped as MEA does.
+pub fn decompress(data: &[u8], size: Option<usize>) -> Result<Vec<u8>, String> {
+    if data.len() < HEADER_SIZE {
+        return Err(String::from("LZMA header truncated"));
+    }
+
+    let mut d = data[0] as u32;
+    if d >= 9 * 5 * 5 {
+        return Err(format!("LZMA properties invalid: {:#X}", d));
+    }
+    let lc = d % 9;
+    d /= 9;
+    let lp = d % 5;
+    let pb = d / 5;
+
+    let mut header_size = [0; 8];
+    header_size.copy_from_slice(&data[5..13]);
+    let header_size = u64::from_le_bytes(header_size);
+    let mut remaining = if header_size == u64::MAX {
+        size
+    } else {
+        Some(header_size as usize)
+    };
+
+    let start = if data.starts_with(&PADDED_HEADER) && data.get(0x0E..0x11) == Some(&[0; 3]) {
+        HEADER_SIZE + 3
+    } else {
+        HEADER_SIZE
+    };
+
+    let mut rc = RangeDecoder::new(&data[start..])?;
+    // Avoid trusting a corrupted size for the initial allocation
+    let mut out = Vec::with_capacity(remaining.unwrap_or(0).min(data.len() * 16));
+
+    let mut literal = vec![PROB_INIT; 0x300 << (lc + lp)];
+    let mut pos_slot = [[PROB_INIT; 1 << 6]; LEN_TO_POS_STATES];
+    let mut pos_decoders = [PROB_INIT; 1 + FULL_DISTANCES - END_POS_MODEL_INDEX as usize];
+    let mut align = [PROB_INIT; 1 << ALIGN_BITS];
+    let mut is_match = [PROB_INIT; STATES << 4];
+    let mut is_rep = [PROB_INIT; STATES];
+    let mut is_rep_g0 = [PROB_INIT; STATES];
+    let mut is_rep_g1 = [PROB_INIT; STATES];
+    let mut is_rep_g2 = [PROB_INIT; STATES];
+    let mut is_rep0_long = [PROB_INIT; STATES << 4];
+    let mut len_decoder = LenDecoder::new();
+    let mut rep_len_decoder = LenDecoder::new();
+
+    let mut state = 0usize;
+    let (mut rep0, mut rep1, mut rep2, mut rep3) = (0usize, 0usize, 0usize, 0usize);
+
+    loop {
+        if remaining == Some(0) {
+            break;
+        }
+
+        let pos_state = out.len() & ((1 << pb) - 1);
+
+        if rc.bit(&mut is_match[(state << 4) + pos_state])? == 0 {
+            let prev = out.last().cloned().unwrap_or(0) as usize;
+            let lit_state = ((out.len() & ((1 << lp) - 1)) << lc) + (prev >> (8 - lc));
+            let probs = &mut literal[0x300 * lit_state..0x300 * (lit_state + 1)];
+
+            let mut symbol = 1usize;
+            if state >= 7 {
+                let mut match_byte = out[out.len() - rep0 - 1] as usize;
+                while symbol < 0x100 {
+                    let match_bit = (match_byte >> 7) & 1;
+                    match_byte <<= 1;
+                    let bit = rc.bit(&mut probs[((1 + match_bit) << 8) + symbol])? as usize;
+                    symbol = (symbol << 1) | bit;
+                    if match_bit != bit {
+                        break;
+                    }
+                }
+            }
+            while symbol < 0x100 {
+                symbol = (symbol << 1) | rc.bit(&mut probs[symbol])? as usize;
+            }
+            out.push((symbol - 0x100) as u8);
+
+            state = match state {
+                0..=3 => 0,
+                4..=9 => state - 3,
+                _ => state - 6,
+            };
+            remaining = remaining.map(|remaining| remaining - 1);
+            continue;
+        }
+
+        let len;
+        if rc.bit(&mut is_rep[state])? != 0 {
+            if out.is_empty() {
+                return Err(String::from("LZMA stream corrupted"));
+            }
+            if rc.bit(&mut is_rep_g0[state])? == 0 {
+                if rc.bit(&mut is_rep0_long[(state << 4) + pos_state])? == 0 {
+                    state = if state < 7 { 9 } else { 11 };
+                    out.push(out[out.len() - rep0 - 1]);
+                    remaining = remaining.map(|remaining| remaining - 1);
+                    continue;
+                }
+            } else {
+                let dist;
+                if rc.bit(&mut is_rep_g1[state])? == 0 {
+                    dist = rep1;
+                } else {
+                    if rc.bit(&mut is_rep_g2[state])? == 0 {
+                        dist = rep2;
+                    } else {
+                        dist = rep3;
+                        rep3 = rep2;
+                    }
+                    rep2 = rep1;
+                }
+                rep1 = rep0;
+                rep0 = dist;
+            }
+            len = rep_len_decoder.decode(&mut rc, pos_state)?;
+            state = if state < 7 { 8 } else { 11 };
+        } else {
+            rep3 = rep2;
+            rep2 = rep1;
+            rep1 = rep0;
+            len = len_decoder.decode(&mut rc, pos_state)?;
+            state = if state < 7 { 7 } else { 10 };
+
+            let len_state = len.min(LEN_TO_POS_STATES - 1);
+            let slot = rc.bit_tree(&mut pos_slot[len_state], 6)?;
+            let dist = if slot < 4 {
+                slot
+            } else {
+                let direct_bits = (slot >> 1) - 1;
+                let base = (2 | (slot & 1)) << direct_bits;
+                if slot < END_POS_MODEL_INDEX {
+                    base + rc.bit_tree_reverse(&mut pos_decoders[(base - slot) as usize..], direct_bits)?
+                } else {
+                    let high = rc.direct_bits(direct_bits - ALIGN_BITS as u32)? << ALIGN_BITS;
+                    base.wrapping_add(high).wrapping_add(rc.bit_tree_reverse(&mut align, ALIGN_BITS as u32)?)
+                }
+            };
+
+            if dist == 0xFFFF_FFFF {
+                // End marker
+                break;
+            }
+            rep0 = dist as usize;
+            if rep0 >= out.len() {
+                return Err(String::from("LZMA stream corrupted"));
+            }
+        }
+
+        let mut len = len + MATCH_MIN_LEN;
+        if let Some(remaining) = remaining.as_mut() {
+            if len > *remaining {
+                return Err(String::from("LZMA stream longer than expected"));
+            }
+            *remaining -= len;
+        }
+        while len > 0 {
+            out.push(out[out.len() - rep0 - 1]);
+            len -= 1;
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `b"romulan " * 12 + b"ME"` with unknown size and an end marker, from `xz --format=lzma`
+    const ROMULAN: [u8; 36] = [
+        0x5D, 0x00, 0x00, 0x01, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x39, 0x1B,
+        0xC9, 0xF4, 0x5F, 0x03, 0xF3, 0xC5, 0x2E, 0x72, 0xB3, 0x8F, 0x0D, 0x57, 0x1C, 0x37, 0xFF, 0xFF,
+        0xF6, 0xDC, 0x80, 0x00,
+    ];
+
+    /// Even bytes below 0x40 three times followed by `b"ME"`, with lc=1, lp=2 and pb=0
+    const EVEN: [u8; 61] = [
+        0x13, 0x00, 0x00, 0x01, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00,
+        0x84, 0x88, 0x8B, 0x03, 0x09, 0x86, 0x19, 0xB9, 0xE7, 0x4E, 0xF9, 0x85, 0x55, 0x27, 0x7E, 0x41,
+        0xFA, 0x69, 0x5C, 0xDA, 0x3E, 0xD9, 0x36, 0xF8, 0x7F, 0xE7, 0xD8, 0x6F, 0x9D, 0x2D, 0x76, 0x14,
+        0x04, 0x63, 0x7A, 0x33, 0x5A, 0x88, 0x96, 0xFF, 0xFF, 0xFC, 0x4D, 0xD0, 0x00,
+    ];
+
+    fn romulan() -> Vec<u8> {
+        let mut data = b"romulan ".repeat(12);
+        data.extend_from_slice(b"ME");
+        data
+    }
+
+    fn even() -> Vec<u8> {
+        let mut data: Vec<u8> = (0..0x40).step_by(2).collect::<Vec<u8>>().repeat(3);
+        data.extend_from_slice(b"ME");
+        data
+    }
+
+    #[test]
+    fn end_marker() {
+        assert_eq!(decompress(&ROMULAN, None), Ok(romulan()));
+        assert_eq!(decompress(&EVEN, None), Ok(even()));
+    }
+
+    #[test]
+    fn known_size() {
+        // Size given by the caller, as for modules with metadata
+        assert_eq!(decompress(&ROMULAN, Some(98)), Ok(romulan()));
+        // A match running past the size is an error
+        assert_eq!(decompress(&ROMULAN, Some(10)), Err(String::from("LZMA stream longer than expected")));
+
+        // Size given by the header
+        let mut data = ROMULAN;
+        data[5..13].copy_from_slice(&98u64.to_le_bytes());
+        assert_eq!(decompress(&data, None), Ok(romulan()));
+    }
+
+    /// `ROMULAN` compressed with lc=0, lp=1, pb=1 and a 16 KiB dictionary as in CSME modules,
+    /// with the size in the header and 3 zero bytes of padding after it
+    const PADDED: [u8; 39] = [
+        0x36, 0x00, 0x40, 0x00, 0x00, 0x62, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x39, 0x1B, 0xCA, 0x4E, 0x28, 0x6D, 0x95, 0xAC, 0x31, 0x57, 0x94, 0x11, 0xE5, 0xE7, 0xEF,
+        0xB4, 0xFF, 0xFF, 0xF9, 0xE3, 0xA0, 0x00,
+    ];
+
+    #[test]
+    fn padded_header() {
+        assert_eq!(decompress(&PADDED, None), Ok(romulan()));
+
+        // The same stream without padding
+        let mut data = PADDED[..HEADER_SIZE].to_vec();
+        data.extend_from_slice(&PADDED[HEADER_SIZE + 3..]);
+        assert_eq!(decompress(&data, None), Ok(romulan()));
+
+        // Other properties are never padded
+        let mut data = PADDED;
+        data[2] = 0x01;
+        assert!(decompress(&data, None).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        for len in 0..ROMULAN.len() {
+            assert!(decompress(&ROMULAN[..len], None).is_err(), "{} bytes", len);
+        }
+        for len in 0..EVEN.len() {
+            assert!(decompress(&EVEN[..len], None).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn corrupted() {
+        let mut data = ROMULAN;
+        data[0] = 9 * 5 * 5;
+        assert_eq!(decompress(&data, None), Err(String::from("LZMA properties invalid: 0xE1")));
+
+        let mut data = ROMULAN;
+        data[HEADER_SIZE] = 1;
+        assert_eq!(decompress(&data, None), Err(String::from("LZMA stream invalid")));
+
+        // Any other change must not panic
+        for i in HEADER_SIZE + 1..EVEN.len() {
+            for bit in 0..8 {
+                let mut data = EVEN;
+                data[i] ^= 1 << bit;
+                let _ = decompress(&data, None);
+                let _ = decompress(&data, Some(even().len()));
+            }
+        }
+    }
+}
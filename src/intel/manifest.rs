@@ -0,0 +1,420 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{fmt, mem};
+use plain::Plain;
+use sha2::{Digest, Sha256};
+
+use super::cpd::Compression;
+
+/// Client System Information extension of a CSE manifest, holding the SKU attributes
+pub const EXT_CLIENT_SYSTEM_INFO: u32 = 0x0C;
+
+/// Offset of the module headers that follow a `$MN2` manifest of ME 6 to 10, like me_cleaner
+pub const MODULE_HEADERS_OFFSET: usize = 0x290;
+
+/// Intel ME code partition manifest header
+#[repr(packed)]
+pub struct Header {
+    pub header_type: u32,
+    /// Header length in dwords
+    pub header_length: u32,
+    pub header_version: u32,
+    pub flags: u32,
+    pub vendor: u32,
+    /// Build date in BCD, as 0xYYYYMMDD
+    pub date: u32,
+    /// Manifest size in dwords
+    pub size: u32,
+    pub tag: [u8; 4],
+    pub num_modules: u32,
+    pub major: u16,
+    pub minor: u16,
+    pub hotfix: u16,
+    pub build: u16,
+    pub svn: u32,
+    pub reserved0: u32,
+    /// Version control number, only used by pre-CSE manifests
+    pub vcn: u32,
+    pub reserved1: [u8; 0x40],
+    /// Public key modulus size in dwords
+    pub modulus_size: u32,
+    /// Public key exponent size in dwords
+    pub exponent_size: u32,
+}
+
+impl Header {
+    pub fn valid(&self) -> bool {
+        self.tag == *b"$MN2" || self.tag == *b"$MAN"
+    }
+
+    pub fn kind(&self) -> ManifestKind {
+        if self.tag == *b"$MAN" {
+            ManifestKind::Legacy
+        } else if self.header_version >= 0x21000 {
+            ManifestKind::Cse
+        } else {
+            ManifestKind::Mn2
+        }
+    }
+
+    /// True if signed with a debug key
+    pub fn debug(&self) -> bool {
+        self.flags & (1 << 31) != 0
+    }
+}
+
+unsafe impl Plain for Header {}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ManifestKind {
+    /// `$MAN` manifest of ME 2 to 5
+    Legacy,
+    /// `$MN2` manifest of ME 6 to 10, TXE 1 and 2, and SPS 2 to 3
+    Mn2,
+    /// `$MN2` manifest of CSE firmware, from ME 11 and TXE 3
+    Cse,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Version {
+    pub major: u16,
+    pub minor: u16,
+    pub hotfix: u16,
+    pub build: u16,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.major, self.minor, self.hotfix, self.build)
+    }
+}
+
+/// Build date decoded from BCD
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    pub fn from_bcd(value: u32) -> Date {
+        let digit = |shift: u32| (value >> shift) & 0xF;
+        Date {
+            year: (digit(28) * 1000 + digit(24) * 100 + digit(20) * 10 + digit(16)) as u16,
+            month: (digit(12) * 10 + digit(8)) as u8,
+            day: (digit(4) * 10 + digit(0)) as u8,
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// `$MME` module header that follows a `$MN2` manifest of ME 6 to 10
+pub struct ModuleHeader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ModuleHeader<'a> {
+    fn word(&self, offset: usize) -> u32 {
+        let bytes = &self.data[offset..offset + 4];
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Module name, such as `BUP`
+    pub fn name(&self) -> &'a str {
+        let name = &self.data[0x04..0x14];
+        let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        core::str::from_utf8(&name[..len]).unwrap_or("")
+    }
+
+    /// Address the module is loaded at, which selects its Huffman chunks
+    pub fn base(&self) -> u32 {
+        self.word(0x34)
+    }
+
+    /// Offset of the module data relative to the manifest
+    pub fn offset(&self) -> usize {
+        self.word(0x38) as usize
+    }
+
+    pub fn uncompressed_size(&self) -> usize {
+        self.word(0x3C) as usize
+    }
+
+    /// Size of the module data in the partition
+    pub fn size(&self) -> usize {
+        self.word(0x40) as usize
+    }
+
+    /// Compression type, or None if unknown
+    pub fn compression(&self) -> Option<Compression> {
+        match (self.word(0x50) >> 4) & 0x7 {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Huffman),
+            2 => Some(Compression::Lzma),
+            _ => None,
+        }
+    }
+}
+
+
+pub struct Manifest<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Manifest<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Manifest<'a>, String> {
+        let header = plain::from_bytes::<Header>(data).map_err(|err| {
+            format!("Manifest header invalid: {:?}", err)
+        })?;
+
+        if ! header.valid() {
+            return Err(String::from("Manifest tag not found"));
+        }
+
+        let manifest = Manifest { data };
+        if manifest.signature_offset() + manifest.modulus_len() > data.len() {
+            return Err(format!("Manifest truncated: {:#X} bytes", data.len()));
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn header(&self) -> &'a Header {
+        plain::from_bytes(self.data).unwrap()
+    }
+
+    pub fn kind(&self) -> ManifestKind {
+        self.header().kind()
+    }
+
+    pub fn version(&self) -> Version {
+        let header = self.header();
+        Version {
+            major: header.major,
+            minor: header.minor,
+            hotfix: header.hotfix,
+            build: header.build,
+        }
+    }
+
+    pub fn date(&self) -> Date {
+        Date::from_bcd(self.header().date)
+    }
+
+    pub fn svn(&self) -> u32 {
+        self.header().svn
+    }
+
+    /// Version control number, which CSE manifests no longer have
+    pub fn vcn(&self) -> Option<u32> {
+        match self.kind() {
+            ManifestKind::Cse => None,
+            _ => Some(self.header().vcn),
+        }
+    }
+
+    fn modulus_len(&self) -> usize {
+        self.header().modulus_size as usize * 4
+    }
+
+    fn exponent_len(&self) -> usize {
+        self.header().exponent_size as usize * 4
+    }
+
+    fn signature_offset(&self) -> usize {
+        mem::size_of::<Header>() + self.modulus_len() + self.exponent_len()
+    }
+
+    /// Size of the header, public key and signature, which module entries follow
+    pub fn signed_size(&self) -> usize {
+        self.signature_offset() + self.modulus_len()
+    }
+
+    /// RSA public key modulus, little endian
+    pub fn modulus(&self) -> &'a [u8] {
+        let start = mem::size_of::<Header>();
+        &self.data[start..start + self.modulus_len()]
+    }
+
+    /// RSA public key exponent, little endian
+    pub fn exponent(&self) -> &'a [u8] {
+        let start = mem::size_of::<Header>() + self.modulus_len();
+        &self.data[start..start + self.exponent_len()]
+    }
+
+    /// RSA signature, little endian
+    pub fn signature(&self) -> &'a [u8] {
+        let start = self.signature_offset();
+        &self.data[start..start + self.modulus_len()]
+    }
+
+    /// Module headers of a `$MN2` manifest of ME 6 to 10, which are 0x60 or 0x80 bytes long
+    /// depending on the version
+    pub fn module_headers(&self) -> Result<Vec<ModuleHeader<'a>>, String> {
+        if self.kind() != ManifestKind::Mn2 {
+            return Err(format!("{:?} manifest has no module headers", self.kind()));
+        }
+
+        let count = self.header().num_modules as usize;
+        let data = self.data.get(MODULE_HEADERS_OFFSET..).unwrap_or(&[]);
+        let tag = |offset: usize| data.get(offset..offset + 4) == Some(b"$MME");
+
+        let size = if ! tag(0) {
+            return Err(String::from("Module headers not found"));
+        } else if tag(0x60) || count == 1 {
+            0x60
+        } else if tag(0x80) {
+            0x80
+        } else {
+            return Err(String::from("Module header size unknown"));
+        };
+
+        let mut headers = Vec::new();
+        for i in 0..count {
+            if ! tag(i * size) || (i + 1) * size > data.len() {
+                return Err(format!("Module headers truncated: {} of {} found", i, count));
+            }
+            headers.push(ModuleHeader {
+                data: &data[i * size..(i + 1) * size],
+            });
+        }
+        Ok(headers)
+    }
+
+    /// Extensions that follow the signature of a CSE manifest, as tag and contents
+    pub fn extensions(&self) -> Vec<(u32, &'a [u8])> {
+        let header = self.header();
+        let start = header.header_length as usize * 4;
+        let end = (header.size as usize * 4).min(self.data.len());
+
+        let mut extensions = Vec::new();
+        let mut i = start;
+        while i + 8 <= end {
+            let word = |offset: usize| {
+                let bytes = &self.data[offset..offset + 4];
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            };
+            let (tag, length) = (word(i), word(i + 4) as usize);
+            if length < 8 || i + length > end {
+                break;
+            }
+            extensions.push((tag, &self.data[i..i + length]));
+            i += length;
+        }
+        extensions
+    }
+
+    /// SKU attributes from the Client System Information extension of a CSE manifest
+    pub fn sku_attributes(&self) -> Option<u64> {
+        let (_, data) = self.extensions().into_iter().find(|(tag, _)| *tag == EXT_CLIENT_SYSTEM_INFO)?;
+        let bytes = data.get(0x28..0x30)?;
+        let mut attributes = [0; 8];
+        attributes.copy_from_slice(bytes);
+        Some(u64::from_le_bytes(attributes))
+    }
+
+    /// SHA-256 hash of the public key modulus and exponent
+    pub fn key_hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.modulus());
+        hasher.update(self.exponent());
+        hasher.finalize().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ME 9.5 manifest with a 2048-bit key, laid out like firmware signed by Intel
+    fn manifest() -> Vec<u8> {
+        let mut data = vec![0; 0x284];
+        let mut write = |offset: usize, value: u32| {
+            data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        write(0x00, 4);
+        write(0x04, 0xA1);
+        write(0x08, 0x10000);
+        write(0x10, 0x8086);
+        write(0x14, 0x2014_0708);
+        write(0x18, 0xA1);
+        write(0x1C, u32::from_le_bytes(*b"$MN2"));
+        write(0x20, 2);
+        write(0x24, 9 | 5 << 16);
+        write(0x28, 10 | 1065 << 16);
+        write(0x2C, 3);
+        write(0x34, 7);
+        write(0x78, 0x40);
+        write(0x7C, 1);
+        for (i, b) in data[0x80..0x180].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        data[0x180..0x184].copy_from_slice(&[0x11, 0x00, 0x00, 0x00]);
+        for b in data[0x184..0x284].iter_mut() {
+            *b = 0x5A;
+        }
+        data
+    }
+
+    #[test]
+    fn header_layout() {
+        assert_eq!(mem::size_of::<Header>(), 0x80);
+    }
+
+    #[test]
+    fn parse() {
+        let data = manifest();
+        let manifest = Manifest::new(&data).unwrap();
+        assert_eq!(manifest.kind(), ManifestKind::Mn2);
+        assert_eq!(format!("{}", manifest.version()), "9.5.10.1065");
+        assert_eq!(format!("{}", manifest.date()), "2014-07-08");
+        assert_eq!(manifest.svn(), 3);
+        assert_eq!(manifest.vcn(), Some(7));
+        assert_eq!(manifest.signed_size(), 0x284);
+        assert_eq!(manifest.modulus(), &data[0x80..0x180]);
+        assert_eq!(manifest.exponent(), &[0x11, 0x00, 0x00, 0x00]);
+        assert!(manifest.signature().iter().all(|&b| b == 0x5A));
+
+        let hash: [u8; 32] = Sha256::digest(&data[0x80..0x184]).into();
+        assert_eq!(manifest.key_hash(), hash);
+    }
+
+    #[test]
+    fn sku_attributes() {
+        let mut data = manifest();
+        // CSE manifest with a Client System Information extension for consumer firmware
+        data[0x08..0x0C].copy_from_slice(&0x21000u32.to_le_bytes());
+        data[0x18..0x1C].copy_from_slice(&0xADu32.to_le_bytes());
+        data.resize(0x2B4, 0);
+        data[0x284..0x288].copy_from_slice(&EXT_CLIENT_SYSTEM_INFO.to_le_bytes());
+        data[0x288..0x28C].copy_from_slice(&0x30u32.to_le_bytes());
+        data[0x2AC] = 0x10;
+
+        let manifest = Manifest::new(&data).unwrap();
+        assert_eq!(manifest.kind(), ManifestKind::Cse);
+        assert_eq!(manifest.extensions().len(), 1);
+        assert_eq!(manifest.sku_attributes(), Some(0x10));
+    }
+
+    #[test]
+    fn truncated() {
+        let data = manifest();
+        assert!(Manifest::new(&data[..0x283]).is_err());
+    }
+}
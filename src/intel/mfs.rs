@@ -0,0 +1,523 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{mem, str};
+use plain::Plain;
+
+use super::crc;
+
+pub const PAGE_SIZE: usize = 0x2000;
+pub const CHUNK_SIZE: usize = 0x40;
+/// Chunks in a system page
+pub const SYS_CHUNKS: usize = 120;
+/// Chunks in a data page
+pub const DATA_CHUNKS: usize = 122;
+
+pub const PAGE_SIGNATURE: u32 = 0xAA557887;
+pub const VOLUME_SIGNATURE: u32 = 0x724F6201;
+pub const BACKUP_SIGNATURE: [u8; 4] = *b"MFSB";
+
+/// File holding Intel configuration
+pub const INTEL_CFG: u16 = 6;
+/// File holding FITC configuration
+pub const FITC_CFG: u16 = 7;
+
+/// CRC-16 with polynomial 0x1021 and initial value 0x3FFF, as used by MFS chunks
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0x3FFFu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc & 0x3FFF
+}
+
+/// Number of system and data chunks in a partition of `pages` pages
+fn layout(pages: usize) -> (usize, usize) {
+    let sys_pages = pages / 12;
+    // One page is kept erased as a spare
+    let data_pages = pages.saturating_sub(sys_pages + 1);
+    (sys_pages * SYS_CHUNKS, data_pages * DATA_CHUNKS)
+}
+
+#[repr(packed)]
+pub struct PageHeader {
+    pub signature: u32,
+    /// Update sequence number, higher is newer
+    pub usn: u32,
+    pub erase_count: u32,
+    pub next_erase: u16,
+    /// Index of the first chunk for data pages, zero for system pages
+    pub first_chunk: u16,
+    pub checksum: u8,
+    pub zero: u8,
+}
+
+impl PageHeader {
+    pub fn valid(&self) -> bool {
+        self.signature == PAGE_SIGNATURE
+    }
+
+    pub fn system(&self) -> bool {
+        self.first_chunk == 0
+    }
+}
+
+unsafe impl Plain for PageHeader {}
+
+#[repr(packed)]
+pub struct VolumeHeader {
+    pub signature: u32,
+    pub version: u32,
+    /// Size of the file system data
+    pub size: u32,
+    pub num_files: u16,
+}
+
+unsafe impl Plain for VolumeHeader {}
+
+/// Header of an MFSB partition, which stores the chunks in order without pages
+#[repr(packed)]
+pub struct BackupHeader {
+    pub signature: [u8; 4],
+    /// CRC-32 of the chunks following the header
+    pub crc32: u32,
+    pub reserved: [u8; 0x18],
+}
+
+impl BackupHeader {
+    pub fn valid(&self) -> bool {
+        self.signature == BACKUP_SIGNATURE
+    }
+}
+
+unsafe impl Plain for BackupHeader {}
+
+/// Record of a configuration file such as intel.cfg
+#[repr(packed)]
+pub struct ConfigRecord {
+    pub name: [u8; 12],
+    pub mode: u16,
+    pub options: u16,
+    pub size: u16,
+    pub uid: u16,
+    pub gid: u16,
+    /// Offset of the contents from the start of the configuration file
+    pub offset: u32,
+}
+
+impl ConfigRecord {
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(self.name.len());
+        str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+
+    pub fn directory(&self) -> bool {
+        self.mode & 0x4000 != 0
+    }
+}
+
+unsafe impl Plain for ConfigRecord {}
+
+/// Configuration file made of records with their contents
+pub struct Config {
+    data: Vec<u8>,
+}
+
+impl Config {
+    pub fn new(data: Vec<u8>) -> Result<Config, String> {
+        if data.len() < 4 {
+            return Err(String::from("MFS config truncated"));
+        }
+        let config = Config { data };
+        let end = 4 + config.count() * mem::size_of::<ConfigRecord>();
+        if end > config.data.len() {
+            return Err(format!("MFS config records truncated: {:#X} > {:#X}", end, config.data.len()));
+        }
+        Ok(config)
+    }
+
+    fn count(&self) -> usize {
+        u32::from_le_bytes([self.data[0], self.data[1], self.data[2], self.data[3]]) as usize
+    }
+
+    pub fn records(&self) -> &[ConfigRecord] {
+        plain::slice_from_bytes_len(&self.data[4..], self.count()).unwrap()
+    }
+
+    /// Contents of a record, if it is a file that fits in the configuration
+    pub fn record_data(&self, record: &ConfigRecord) -> Option<&[u8]> {
+        if record.directory() {
+            return None;
+        }
+        let start = record.offset as usize;
+        self.data.get(start..start + record.size as usize)
+    }
+}
+
+/// ME File System, from the MFS or MFSB partition of ME 11 and later
+pub struct Mfs<'a> {
+    /// Chunks by index, system chunks first followed by data chunks
+    chunks: Vec<Option<&'a [u8]>>,
+    sys_chunks: usize,
+    num_files: usize,
+    fat: Vec<u16>,
+    bad_chunks: Vec<usize>,
+}
+
+impl<'a> Mfs<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Mfs<'a>, String> {
+        if data.starts_with(&BACKUP_SIGNATURE) {
+            return Self::backup(data);
+        }
+
+        let (sys_chunks, data_chunks) = layout(data.len() / PAGE_SIZE);
+        let mut chunks: Vec<Option<&'a [u8]>> = vec![None; sys_chunks + data_chunks];
+        let mut bad_chunks = Vec::new();
+
+        let mut headers: Vec<(&PageHeader, &'a [u8])> = Vec::new();
+        for page in data.chunks_exact(PAGE_SIZE) {
+            let header = plain::from_bytes::<PageHeader>(page).map_err(|err| {
+                format!("MFS page header invalid: {:?}", err)
+            })?;
+            if header.valid() {
+                headers.push((header, page));
+            }
+        }
+        if headers.is_empty() {
+            return Err(String::from("MFS pages not found"));
+        }
+        // Newer copies of a chunk replace older ones
+        headers.sort_by_key(|(header, _)| header.usn);
+
+        for (header, page) in headers {
+            let chunk = |i: usize, start: usize| &page[start + i * (CHUNK_SIZE + 2)..][..CHUNK_SIZE + 2];
+
+            if header.system() {
+                let indexes = mem::size_of::<PageHeader>();
+                let start = indexes + (SYS_CHUNKS + 1) * 2;
+                let mut index = 0u16;
+                for i in 0..SYS_CHUNKS {
+                    let value = u16::from_le_bytes([page[indexes + i * 2], page[indexes + i * 2 + 1]]);
+                    if value & 0xC000 != 0 {
+                        break;
+                    }
+                    index = value ^ crc16(&index.to_le_bytes());
+                    let bytes = chunk(i, start);
+                    Self::insert(&mut chunks, &mut bad_chunks, index as usize, bytes)?;
+                }
+            } else {
+                let free = mem::size_of::<PageHeader>();
+                let start = free + DATA_CHUNKS;
+                for i in 0..DATA_CHUNKS {
+                    if page[free + i] == 0xFF {
+                        continue;
+                    }
+                    let bytes = chunk(i, start);
+                    Self::insert(&mut chunks, &mut bad_chunks, header.first_chunk as usize + i, bytes)?;
+                }
+            }
+        }
+
+        Self::from_chunks(chunks, sys_chunks, bad_chunks)
+    }
+
+    /// Read an MFSB partition, where the system chunks and then the data chunks follow the header
+    fn backup(data: &'a [u8]) -> Result<Mfs<'a>, String> {
+        let header = plain::from_bytes::<BackupHeader>(data).map_err(|err| {
+            format!("MFSB header invalid: {:?}", err)
+        })?;
+        if ! header.valid() {
+            return Err(String::from("MFSB not found"));
+        }
+
+        let body = &data[mem::size_of::<BackupHeader>()..];
+        let expected = header.crc32;
+        if crc::crc32(body) != expected {
+            return Err(format!("MFSB CRC invalid: {:#X}", expected));
+        }
+
+        // The chunk counts are those of the MFS partition that was backed up
+        let total = body.len() / CHUNK_SIZE;
+        let (sys_chunks, _) = (0..=total / SYS_CHUNKS + 1).map(layout).find(|&(sys_chunks, data_chunks)| {
+            sys_chunks > 0 && sys_chunks + data_chunks == total
+        }).ok_or_else(|| format!("MFSB size invalid: {:#X}", body.len()))?;
+
+        let chunks = body.chunks_exact(CHUNK_SIZE).map(Some).collect();
+        Self::from_chunks(chunks, sys_chunks, Vec::new())
+    }
+
+    fn from_chunks(chunks: Vec<Option<&'a [u8]>>, sys_chunks: usize, bad_chunks: Vec<usize>) -> Result<Mfs<'a>, String> {
+        let data_chunks = chunks.len() - sys_chunks;
+        let mut mfs = Mfs {
+            chunks,
+            sys_chunks,
+            num_files: 0,
+            fat: Vec::new(),
+            bad_chunks,
+        };
+
+        let system = mfs.system();
+        let volume = plain::from_bytes::<VolumeHeader>(&system).map_err(|err| {
+            format!("MFS volume header invalid: {:?}", err)
+        })?;
+        let signature = volume.signature;
+        if signature != VOLUME_SIGNATURE {
+            return Err(format!("MFS volume signature invalid: {:#X}", signature));
+        }
+
+        mfs.num_files = volume.num_files as usize;
+        let fat_start = mem::size_of::<VolumeHeader>();
+        let fat_len = mfs.num_files + data_chunks;
+        if fat_start + fat_len * 2 > system.len() {
+            return Err(String::from("MFS allocation table truncated"));
+        }
+        mfs.fat = system[fat_start..fat_start + fat_len * 2].chunks(2).map(|b| {
+            u16::from_le_bytes([b[0], b[1]])
+        }).collect();
+
+        Ok(mfs)
+    }
+
+    /// Store a chunk if its CRC, which covers the data and chunk index, is valid
+    fn insert(chunks: &mut [Option<&'a [u8]>], bad_chunks: &mut Vec<usize>, index: usize, bytes: &'a [u8]) -> Result<(), String> {
+        let slot = chunks.get_mut(index).ok_or_else(|| format!("MFS chunk {} out of range", index))?;
+
+        let mut crc_data = [0; CHUNK_SIZE + 2];
+        crc_data[..CHUNK_SIZE].copy_from_slice(&bytes[..CHUNK_SIZE]);
+        crc_data[CHUNK_SIZE..].copy_from_slice(&(index as u16).to_le_bytes());
+        let crc = u16::from_le_bytes([bytes[CHUNK_SIZE], bytes[CHUNK_SIZE + 1]]);
+        if crc16(&crc_data) == crc {
+            *slot = Some(&bytes[..CHUNK_SIZE]);
+        } else {
+            bad_chunks.push(index);
+        }
+        Ok(())
+    }
+
+    /// Indexes of chunks that were skipped because their CRC is invalid
+    pub fn bad_chunks(&self) -> &[usize] {
+        &self.bad_chunks
+    }
+
+    /// Contents of the system area, holding the volume header and allocation table
+    pub fn system(&self) -> Vec<u8> {
+        let mut system = Vec::with_capacity(self.sys_chunks * CHUNK_SIZE);
+        for chunk in &self.chunks[..self.sys_chunks] {
+            system.extend_from_slice(chunk.unwrap_or(&[0xFF; CHUNK_SIZE]));
+        }
+        system
+    }
+
+    pub fn num_files(&self) -> usize {
+        self.num_files
+    }
+
+    /// Contents of a file by index, or `None` if it is not present
+    pub fn file(&self, index: u16) -> Result<Option<Vec<u8>>, String> {
+        let mut value = *self.fat.get(index as usize).ok_or_else(|| {
+            format!("MFS file {} out of range", index)
+        })?;
+        if value == 0x0000 || value >= 0xFFFE {
+            return Ok(None);
+        }
+
+        let mut data = Vec::new();
+        // Entries below the file count give the bytes used in the last chunk
+        while value as usize >= self.num_files {
+            let chunk_index = value as usize + self.sys_chunks - self.num_files;
+            let chunk = self.chunks.get(chunk_index).cloned().flatten().ok_or_else(|| {
+                format!("MFS file {} chunk {} missing", index, chunk_index)
+            })?;
+            data.extend_from_slice(chunk);
+
+            value = *self.fat.get(value as usize).ok_or_else(|| {
+                format!("MFS file {} chain invalid", index)
+            })?;
+            if data.len() > self.fat.len() * CHUNK_SIZE {
+                return Err(format!("MFS file {} chain loops", index));
+            }
+        }
+
+        let used = value as usize;
+        if used > CHUNK_SIZE || data.is_empty() {
+            return Err(format!("MFS file {} chain invalid", index));
+        }
+        data.truncate(data.len() - CHUNK_SIZE + used);
+        Ok(Some(data))
+    }
+
+    /// Every file present with its index, or the error reading it
+    pub fn files(&self) -> Vec<(u16, Result<Vec<u8>, String>)> {
+        let mut files = Vec::new();
+        for index in 0..self.num_files as u16 {
+            match self.file(index) {
+                Ok(Some(data)) => files.push((index, Ok(data))),
+                Ok(None) => (),
+                Err(err) => files.push((index, Err(err))),
+            }
+        }
+        files
+    }
+
+    /// Configuration file by index, such as `INTEL_CFG`, or `None` if it is not present
+    pub fn config(&self, index: u16) -> Result<Option<Config>, String> {
+        match self.file(index)? {
+            Some(data) => Config::new(data).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::TryInto;
+
+    const NUM_FILES: usize = 256;
+
+    /// Logical chunks of a 12 page partition with a 100 byte file 1 and an intel.cfg
+    fn chunks() -> (usize, Vec<[u8; CHUNK_SIZE]>) {
+        let (sys_chunks, data_chunks) = layout(12);
+        let mut system = vec![0xFF; sys_chunks * CHUNK_SIZE];
+        system[0..4].copy_from_slice(&VOLUME_SIGNATURE.to_le_bytes());
+        system[4..8].copy_from_slice(&1u32.to_le_bytes());
+        system[8..12].copy_from_slice(&(((sys_chunks + data_chunks) * CHUNK_SIZE) as u32).to_le_bytes());
+        system[12..14].copy_from_slice(&(NUM_FILES as u16).to_le_bytes());
+        let mut fat = |index: usize, value: usize| {
+            let offset = mem::size_of::<VolumeHeader>() + index * 2;
+            system[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes());
+        };
+        // File 1 uses data chunks 0 and 1, with 36 bytes in the last
+        fat(1, NUM_FILES);
+        fat(NUM_FILES, NUM_FILES + 1);
+        fat(NUM_FILES + 1, 36);
+        // intel.cfg uses data chunk 2
+        fat(INTEL_CFG as usize, NUM_FILES + 2);
+        fat(NUM_FILES + 2, 4 + mem::size_of::<ConfigRecord>() + 3);
+
+        let mut chunks: Vec<[u8; CHUNK_SIZE]> = system.chunks(CHUNK_SIZE).map(|chunk| chunk.try_into().unwrap()).collect();
+        chunks.resize(sys_chunks + data_chunks, [0xFF; CHUNK_SIZE]);
+        for i in 0..2 {
+            chunks[sys_chunks + i] = [i as u8 + 1; CHUNK_SIZE];
+        }
+        let config = &mut chunks[sys_chunks + 2];
+        *config = [0; CHUNK_SIZE];
+        config[0..4].copy_from_slice(&1u32.to_le_bytes());
+        config[4..13].copy_from_slice(b"test.conf");
+        config[4 + 16..4 + 18].copy_from_slice(&3u16.to_le_bytes());
+        config[4 + 22..4 + 26].copy_from_slice(&(4 + mem::size_of::<ConfigRecord>() as u32).to_le_bytes());
+        config[30..33].copy_from_slice(b"abc");
+        (sys_chunks, chunks)
+    }
+
+    fn chunk_crc(chunk: &[u8], index: usize) -> [u8; 2] {
+        let mut crc_data = chunk.to_vec();
+        crc_data.extend_from_slice(&(index as u16).to_le_bytes());
+        crc16(&crc_data).to_le_bytes()
+    }
+
+    fn page_header(page: &mut [u8], first_chunk: u16) {
+        page[0..4].copy_from_slice(&PAGE_SIGNATURE.to_le_bytes());
+        page[4..8].copy_from_slice(&1u32.to_le_bytes());
+        page[14..16].copy_from_slice(&first_chunk.to_le_bytes());
+    }
+
+    /// MFS partition with the system chunks in page 0 and the first data chunks in page 1
+    fn partition() -> Vec<u8> {
+        let (sys_chunks, chunks) = chunks();
+        let mut data = vec![0xFF; 12 * PAGE_SIZE];
+        let header = mem::size_of::<PageHeader>();
+
+        let page = &mut data[..PAGE_SIZE];
+        page_header(page, 0);
+        let start = header + (SYS_CHUNKS + 1) * 2;
+        let mut previous = 0u16;
+        for i in 0..sys_chunks {
+            let value = i as u16 ^ crc16(&previous.to_le_bytes());
+            page[header + i * 2..header + i * 2 + 2].copy_from_slice(&value.to_le_bytes());
+            previous = i as u16;
+            let offset = start + i * (CHUNK_SIZE + 2);
+            page[offset..offset + CHUNK_SIZE].copy_from_slice(&chunks[i]);
+            page[offset + CHUNK_SIZE..offset + CHUNK_SIZE + 2].copy_from_slice(&chunk_crc(&chunks[i], i));
+        }
+
+        let page = &mut data[PAGE_SIZE..2 * PAGE_SIZE];
+        page_header(page, sys_chunks as u16);
+        let start = header + DATA_CHUNKS;
+        for i in 0..3 {
+            page[header + i] = 0;
+            let index = sys_chunks + i;
+            let offset = start + i * (CHUNK_SIZE + 2);
+            page[offset..offset + CHUNK_SIZE].copy_from_slice(&chunks[index]);
+            page[offset + CHUNK_SIZE..offset + CHUNK_SIZE + 2].copy_from_slice(&chunk_crc(&chunks[index], index));
+        }
+
+        data
+    }
+
+    fn check_files(mfs: &Mfs) {
+        assert_eq!(mfs.num_files(), NUM_FILES);
+        let mut expected = vec![1; CHUNK_SIZE];
+        expected.extend_from_slice(&[2; 36]);
+        assert_eq!(mfs.file(1), Ok(Some(expected)));
+        assert_eq!(mfs.file(2), Ok(None));
+
+        let config = mfs.config(INTEL_CFG).unwrap().unwrap();
+        assert_eq!(config.records().len(), 1);
+        let record = &config.records()[0];
+        assert_eq!(record.name(), "test.conf");
+        assert_eq!(config.record_data(record), Some(&b"abc"[..]));
+    }
+
+    #[test]
+    fn pages() {
+        let data = partition();
+        let mfs = Mfs::new(&data).unwrap();
+        assert!(mfs.bad_chunks().is_empty());
+        check_files(&mfs);
+        assert_eq!(mfs.files().len(), 2);
+    }
+
+    #[test]
+    fn bad_chunk() {
+        let mut data = partition();
+        // Corrupt the first data chunk, used by file 1
+        let offset = PAGE_SIZE + mem::size_of::<PageHeader>() + DATA_CHUNKS;
+        data[offset] ^= 0xFF;
+
+        let mfs = Mfs::new(&data).unwrap();
+        let (sys_chunks, _) = layout(12);
+        assert_eq!(mfs.bad_chunks(), &[sys_chunks]);
+        assert!(mfs.file(1).is_err());
+        assert!(mfs.config(INTEL_CFG).unwrap().is_some());
+
+        let files = mfs.files();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].1.is_err());
+        assert!(files[1].1.is_ok());
+    }
+
+    #[test]
+    fn backup() {
+        let (_, chunks) = chunks();
+        let body: Vec<u8> = chunks.iter().flatten().copied().collect();
+        let mut data = BACKUP_SIGNATURE.to_vec();
+        data.extend_from_slice(&crc::crc32(&body).to_le_bytes());
+        data.extend_from_slice(&[0xFF; 0x18]);
+        data.extend_from_slice(&body);
+
+        let mfs = Mfs::new(&data).unwrap();
+        check_files(&mfs);
+
+        data[0x20] ^= 0xFF;
+        assert!(Mfs::new(&data).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{fmt, mem};
+use plain::Plain;
+use uefi::guid::Guid;
+
+/// FFS file holding the microcode updates of a BIOS region
+pub const FILE_GUID: Guid = Guid(0x197DB236, 0xF856, 0x4924, [0x90, 0xF8, 0xCD, 0xF1, 0x2F, 0xB8, 0x75, 0xF3]);
+
+/// FFS file holding the microcode updates of an AMI BIOS region
+pub const AMI_FILE_GUID: Guid = Guid(0x17088572, 0x377F, 0x44EF, [0x8F, 0x4E, 0xB0, 0x9F, 0xFF, 0x46, 0xA0, 0x70]);
+
+/// Size of an update with a data size of zero
+pub const DEFAULT_DATA_SIZE: usize = 2000;
+pub const DEFAULT_TOTAL_SIZE: usize = 2048;
+
+/// Intel microcode update header
+#[repr(packed)]
+pub struct Header {
+    pub header_version: u32,
+    pub revision: u32,
+    /// Release date in BCD, as 0xMMDDYYYY
+    pub date: u32,
+    pub processor_signature: u32,
+    pub checksum: u32,
+    pub loader_revision: u32,
+    pub processor_flags: u32,
+    pub data_size: u32,
+    pub total_size: u32,
+    pub reserved: [u32; 3],
+}
+
+impl Header {
+    pub fn valid(&self) -> bool {
+        self.header_version == 1 && self.loader_revision == 1
+    }
+}
+
+unsafe impl Plain for Header {}
+
+#[repr(packed)]
+pub struct ExtendedTableHeader {
+    pub count: u32,
+    pub checksum: u32,
+    pub reserved: [u8; 12],
+}
+
+unsafe impl Plain for ExtendedTableHeader {}
+
+#[repr(packed)]
+pub struct ExtendedSignature {
+    pub processor_signature: u32,
+    pub processor_flags: u32,
+    pub checksum: u32,
+}
+
+unsafe impl Plain for ExtendedSignature {}
+
+/// CPUID signature split into family, model and stepping
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Signature(pub u32);
+
+impl Signature {
+    pub fn family(&self) -> u32 {
+        let family = (self.0 >> 8) & 0xF;
+        if family == 0xF {
+            family + ((self.0 >> 20) & 0xFF)
+        } else {
+            family
+        }
+    }
+
+    pub fn model(&self) -> u32 {
+        let family = (self.0 >> 8) & 0xF;
+        let model = (self.0 >> 4) & 0xF;
+        if family == 0x6 || family == 0xF {
+            model | ((self.0 >> 16) & 0xF) << 4
+        } else {
+            model
+        }
+    }
+
+    pub fn stepping(&self) -> u32 {
+        self.0 & 0xF
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:05X} ({:02X}-{:02X}-{:X})", self.0, self.family(), self.model(), self.stepping())
+    }
+}
+
+fn sum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, dword| {
+        let mut bytes = [0; 4];
+        bytes[..dword.len()].copy_from_slice(dword);
+        sum.wrapping_add(u32::from_le_bytes(bytes))
+    })
+}
+
+/// Intel microcode update
+pub struct Microcode<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Microcode<'a> {
+    /// Parse an update at the start of `data`, which may be followed by other data
+    pub fn new(data: &'a [u8]) -> Result<Microcode<'a>, String> {
+        let header = plain::from_bytes::<Header>(data).map_err(|err| {
+            format!("Microcode header invalid: {:?}", err)
+        })?;
+        if ! header.valid() {
+            return Err(String::from("Microcode header version invalid"));
+        }
+
+        let microcode = Microcode { data };
+        let (data_size, total_size) = (microcode.data_size(), microcode.total_size());
+        if total_size < data_size + mem::size_of::<Header>() || total_size % 4 != 0 {
+            return Err(format!("Microcode size invalid: data {:#X}, total {:#X}", data_size, total_size));
+        }
+        if total_size > data.len() {
+            return Err(format!("Microcode truncated: {:#X} > {:#X}", total_size, data.len()));
+        }
+
+        Ok(Microcode {
+            data: &data[..total_size],
+        })
+    }
+
+    /// The whole update, including headers and extended signatures
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn header(&self) -> &'a Header {
+        plain::from_bytes(self.data).unwrap()
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.header().revision
+    }
+
+    pub fn signature(&self) -> Signature {
+        Signature(self.header().processor_signature)
+    }
+
+    pub fn processor_flags(&self) -> u32 {
+        self.header().processor_flags
+    }
+
+    /// Release date as year, month and day
+    pub fn date(&self) -> (u16, u8, u8) {
+        let date = self.header().date;
+        let bcd = |value: u32| (value >> 4) * 10 + (value & 0xF);
+        let year = bcd((date >> 8) & 0xFF) * 100 + bcd(date & 0xFF);
+        (year as u16, bcd(date >> 24) as u8, bcd((date >> 16) & 0xFF) as u8)
+    }
+
+    pub fn data_size(&self) -> usize {
+        match self.header().data_size {
+            0 => DEFAULT_DATA_SIZE,
+            size => size as usize,
+        }
+    }
+
+    pub fn total_size(&self) -> usize {
+        match self.header().data_size {
+            0 => DEFAULT_TOTAL_SIZE,
+            _ => self.header().total_size as usize,
+        }
+    }
+
+    /// True if the dwords of the whole update sum to zero
+    pub fn checksum_valid(&self) -> bool {
+        sum(self.data) == 0
+    }
+
+    fn extended_offset(&self) -> Option<usize> {
+        let offset = mem::size_of::<Header>() + self.data_size();
+        if offset + mem::size_of::<ExtendedTableHeader>() <= self.data.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    pub fn extended_header(&self) -> Option<&'a ExtendedTableHeader> {
+        plain::from_bytes(&self.data[self.extended_offset()?..]).ok()
+    }
+
+    /// Other processors supported by this update
+    pub fn extended_signatures(&self) -> &'a [ExtendedSignature] {
+        let (offset, header) = match (self.extended_offset(), self.extended_header()) {
+            (Some(offset), Some(header)) => (offset, header),
+            _ => return &[],
+        };
+        let start = offset + mem::size_of::<ExtendedTableHeader>();
+        plain::slice_from_bytes_len(&self.data[start..], header.count as usize).unwrap_or(&[])
+    }
+
+    /// True if there is no extended signature table, or its dwords sum to zero
+    pub fn extended_checksum_valid(&self) -> bool {
+        match self.extended_offset() {
+            Some(offset) => {
+                let len = mem::size_of::<ExtendedTableHeader>() + mem::size_of_val(self.extended_signatures());
+                sum(&self.data[offset..offset + len]) == 0
+            },
+            None => true,
+        }
+    }
+
+    /// Processor signatures and flags supported by this update, including extended signatures
+    pub fn processors(&self) -> Vec<(Signature, u32)> {
+        let mut processors = vec![(self.signature(), self.processor_flags())];
+        for extended in self.extended_signatures() {
+            processors.push((Signature(extended.processor_signature), extended.processor_flags));
+        }
+        processors
+    }
+}
+
+/// True if two updates apply to the same processor signature and platform
+pub fn same_processor(a: &Microcode, b: &Microcode) -> bool {
+    let (a_flags, b_flags) = (a.processor_flags(), b.processor_flags());
+    a.signature() == b.signature() && (a_flags & b_flags != 0 || a_flags == 0 || b_flags == 0)
+}
+
+/// Find updates in `data` at 16 byte alignment, returning their offsets
+pub fn scan(data: &[u8]) -> Vec<(usize, Microcode<'_>)> {
+    let mut updates = Vec::new();
+    let mut i = 0;
+    while i + mem::size_of::<Header>() <= data.len() {
+        if let Ok(microcode) = Microcode::new(&data[i..]) {
+            if microcode.checksum_valid() {
+                i += microcode.total_size();
+                updates.push((i - microcode.total_size(), microcode));
+                continue;
+            }
+        }
+        i += 16;
+    }
+    updates
+}
@@ -1,9 +1,10 @@
 // SPDX-License-Identifier: MIT
 
 use alloc::string::String;
-use core::{fmt, mem};
+use alloc::vec::Vec;
+use core::{cmp, fmt, mem};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[repr(usize)]
 pub enum RegionKind {
     Descriptor = 0,
@@ -11,10 +12,44 @@ pub enum RegionKind {
     ManagementEngine = 2,
     Ethernet = 3,
     PlatformData = 4,
+    /// Device Expansion on IFDv2
     Reserved5 = 5,
+    /// Secondary BIOS on IFDv2
     Reserved6 = 6,
     Reserved7 = 7,
     EmbeddedController = 8,
+    DeviceExpansion2 = 9,
+    InnovationEngine = 10,
+    Ethernet10G0 = 11,
+    Ethernet10G1 = 12,
+    Reserved13 = 13,
+    Reserved14 = 14,
+    Ptt = 15,
+}
+
+impl RegionKind {
+    pub const ALL: [RegionKind; 16] = [
+        RegionKind::Descriptor,
+        RegionKind::Bios,
+        RegionKind::ManagementEngine,
+        RegionKind::Ethernet,
+        RegionKind::PlatformData,
+        RegionKind::Reserved5,
+        RegionKind::Reserved6,
+        RegionKind::Reserved7,
+        RegionKind::EmbeddedController,
+        RegionKind::DeviceExpansion2,
+        RegionKind::InnovationEngine,
+        RegionKind::Ethernet10G0,
+        RegionKind::Ethernet10G1,
+        RegionKind::Reserved13,
+        RegionKind::Reserved14,
+        RegionKind::Ptt,
+    ];
+
+    pub fn from_index(index: usize) -> Option<RegionKind> {
+        RegionKind::ALL.get(index).cloned()
+    }
 }
 
 impl fmt::Display for RegionKind {
@@ -25,7 +60,14 @@ impl fmt::Display for RegionKind {
             RegionKind::ManagementEngine => "Intel ME",
             RegionKind::Ethernet => "GbE",
             RegionKind::PlatformData => "Platform Data",
+            RegionKind::Reserved5 => "Device Expansion",
+            RegionKind::Reserved6 => "Secondary BIOS",
             RegionKind::EmbeddedController => "EC",
+            RegionKind::DeviceExpansion2 => "Device Expansion 2",
+            RegionKind::InnovationEngine => "IE",
+            RegionKind::Ethernet10G0 => "10GbE 0",
+            RegionKind::Ethernet10G1 => "10GbE 1",
+            RegionKind::Ptt => "PTT",
             _ => "Reserved",
         };
         write!(f, "{}", name)
@@ -34,9 +76,27 @@ impl fmt::Display for RegionKind {
 
 pub const HAP: u32 = 0x10000;
 
+pub use self::chipset::{Chipset, ChipsetGuess, Confidence};
+
+pub mod acm;
+pub mod bootguard;
+pub mod chipset;
+pub mod clean;
+pub mod cpd;
+pub mod crc;
+pub mod family;
 pub mod file;
+pub mod fit;
+pub mod fpt;
 pub mod flash;
+pub mod gbe;
+pub mod lzma;
+pub mod manifest;
+pub mod mfs;
+pub mod microcode;
 pub mod section;
+pub mod strap;
+pub mod validate;
 pub mod volume;
 
 pub struct Rom<'a> {
@@ -73,7 +133,7 @@ impl<'a> Rom<'a> {
     }
 
     pub fn flash_region(&self) -> Result<&'a flash::Region, String> {
-        let offset = (((self.descriptor.map0 >> 16) & 0xff) << 4) as usize;
+        let offset = self.descriptor.frba();
 
         if offset >= self.data.len() {
             return Err(format!("Flash region table truncated"))
@@ -84,8 +144,63 @@ impl<'a> Rom<'a> {
         })
     }
 
+    pub fn flash_component(&self) -> Result<&'a flash::Component, String> {
+        let offset = self.descriptor.fcba();
+
+        if offset >= self.data.len() {
+            return Err(String::from("Flash component table truncated"))
+        }
+
+        plain::from_bytes(&self.data[offset..]).map_err(|err| {
+            format!("Flash component table invalid: {:?}", err)
+        })
+    }
+
+    /// Number of flash chips described by the component table
+    pub fn flash_components(&self) -> usize {
+        (((self.descriptor.map0 >> 8) & 0x3) + 1) as usize
+    }
+
+    /// Detect the descriptor version from the read clock frequency, like ifdtool
+    pub fn descriptor_version(&self) -> Result<flash::Version, String> {
+        let component = self.flash_component()?;
+        match (component.comp >> 17) & 0x7 {
+            0 => Ok(flash::Version::V1),
+            4 | 6 => Ok(flash::Version::V2),
+            unknown => Err(format!("Flash descriptor version unknown: read clock {}", unknown)),
+        }
+    }
+
+    /// Total size in bytes of all flash chips declared by the component table
+    pub fn flash_density(&self) -> Result<usize, String> {
+        let version = self.descriptor_version()?;
+        let component = self.flash_component()?;
+
+        let mut density = 0;
+        for chip in 0..self.flash_components() {
+            density += component.density(chip, version).ok_or_else(|| {
+                format!("Flash component {} density invalid", chip)
+            })?;
+        }
+        Ok(density)
+    }
+
+    /// Returns a warning if the declared flash density does not match the image length
+    pub fn flash_density_warning(&self) -> Result<Option<String>, String> {
+        let density = self.flash_density()?;
+        if density == self.data.len() {
+            Ok(None)
+        } else {
+            Ok(Some(format!(
+                "Flash density {} K does not match image length {} K",
+                density / 1024,
+                self.data.len() / 1024
+            )))
+        }
+    }
+
     pub fn flash_pchstrap(&self) -> Result<&'a flash::PchStrap, String> {
-        let offset = (((self.descriptor.map1 >> 16) & 0xff) << 4) as usize;
+        let offset = self.descriptor.fpsba();
 
         if offset >= self.data.len() {
             return Err(format!("PCHSTRAP table truncated"))
@@ -96,27 +211,154 @@ impl<'a> Rom<'a> {
         })
     }
 
-    pub fn high_assurance_platform(&self) -> Result<bool, String> {
-        let pchstrap = self.flash_pchstrap()?;
-        Ok(pchstrap.data[0] & HAP == HAP)
+    pub fn flash_master(&self) -> Result<&'a flash::Master, String> {
+        let offset = self.descriptor.fmba();
+
+        if offset >= self.data.len() {
+            return Err(String::from("Flash master table truncated"))
+        }
+
+        plain::from_bytes(&self.data[offset..]).map_err(|err| {
+            format!("Flash master table invalid: {:?}", err)
+        })
     }
 
-    pub fn get_region_base_limit(&self, kind: RegionKind) -> Result<Option<(usize, usize)>, String> {
-        let frba = self.flash_region()?;
+    /// Read and write permissions of each flash master
+    pub fn master_access(&self) -> Result<Vec<flash::MasterAccess>, String> {
+        let version = self.descriptor_version()?;
+        Ok(self.flash_master()?.access(version))
+    }
 
-        let reg = frba.data[kind as usize];
+    /// Master permissions that leave the descriptor or ME open to modification
+    pub fn master_access_issues(&self) -> Result<Vec<flash::AccessIssue>, String> {
+        Ok(self.master_access()?.iter().flat_map(|access| access.issues()).collect())
+    }
 
-        let base_mask = 0x7fff;
-        let limit_mask = base_mask << 16;
+    /// True if no master can write the descriptor, and only the ME can write the ME region
+    pub fn descriptor_locked(&self) -> Result<bool, String> {
+        Ok(self.master_access_issues()?.is_empty())
+    }
 
-    	let base = (reg & base_mask) << 12;
-    	let limit = ((reg & limit_mask) >> 4) | 0xfff;
+    /// Entries of the VSCC table, found through the upper map
+    pub fn vscc_table(&self) -> Result<&'a [flash::VsccEntry], String> {
+        let offset = self.descriptor.vtba();
 
-        if limit > base {
-            Ok(Some((base as usize, limit as usize)))
-        } else {
-            Ok(None)
+        if offset >= self.data.len() {
+            return Err(String::from("VSCC table truncated"))
+        }
+
+        plain::slice_from_bytes_len(
+            &self.data[offset..],
+            self.descriptor.vtl() / 2
+        ).map_err(|err| {
+            format!("VSCC table invalid: {:?}", err)
+        })
+    }
+
+    /// Number of PCH strap dwords declared by the descriptor
+    pub fn pch_strap_len(&self) -> usize {
+        ((self.descriptor.map1 >> 24) & 0xff) as usize
+    }
+
+    pub fn flash_procstrap(&self) -> Result<&'a flash::ProcStrap, String> {
+        let offset = self.descriptor.fmsba();
+
+        if offset >= self.data.len() {
+            return Err(String::from("PROCSTRAP table truncated"))
+        }
+
+        plain::from_bytes(&self.data[offset..]).map_err(|err| {
+            format!("PROCSTRAP table invalid: {:?}", err)
+        })
+    }
+
+    /// Number of processor strap dwords declared by the descriptor
+    pub fn proc_strap_len(&self) -> usize {
+        ((self.descriptor.map2 >> 8) & 0xff) as usize
+    }
+
+    /// Read the strap dword that holds a field
+    pub fn strap(&self, field: &strap::StrapField) -> Result<u32, String> {
+        let (len, count) = match field.table {
+            strap::StrapTable::Pch => (self.pch_strap_len(), mem::size_of::<flash::PchStrap>() / 4),
+            strap::StrapTable::Processor => (self.proc_strap_len(), mem::size_of::<flash::ProcStrap>() / 4),
+        };
+
+        if field.index >= len || field.index >= count {
+            return Err(format!("{:?} strap {} not present for {}", field.table, field.index, field.name));
+        }
+
+        match field.table {
+            strap::StrapTable::Pch => Ok(self.flash_pchstrap()?.data[field.index]),
+            strap::StrapTable::Processor => Ok(self.flash_procstrap()?.data[field.index]),
+        }
+    }
+
+    /// Decode the soft straps listed by `strap::fields` for a chipset generation
+    pub fn straps(&self, chipset: Chipset) -> Result<Vec<strap::StrapValue>, String> {
+        let mut values = Vec::new();
+        for field in strap::fields(chipset) {
+            let value = field.get(self.strap(field)?);
+            values.push(strap::StrapValue { field, value });
         }
+        Ok(values)
+    }
+
+    /// Guess the chipset generation from the descriptor map, strap lengths,
+    /// component table and ME firmware family and version
+    pub fn chipset(&self) -> Result<ChipsetGuess, String> {
+        let version = self.descriptor_version()?;
+        let descriptor = chipset::from_descriptor(self.descriptor, self.flash_component()?, version);
+
+        let me = self.me().ok().flatten().and_then(|me| {
+            let family = me.family().ok()?;
+            let manifest = me.manifest().ok()??;
+            chipset::from_me_version(family, manifest.version().major)
+        });
+
+        Ok(ChipsetGuess::new(version, descriptor, me))
+    }
+
+    /// Chipset generation used to pick the ME disable straps. IFDv2 always uses HAP, and an
+    /// unknown version is treated as IFDv2 like for regions. IFDv1 only uses the descriptor to
+    /// tell ICH from PCH straps, without the ME heuristics of `chipset`
+    fn me_disable_chipset(&self) -> Result<Chipset, String> {
+        match self.descriptor_version() {
+            Ok(flash::Version::V1) => {
+                let component = self.flash_component()?;
+                Ok(chipset::from_descriptor(self.descriptor, component, flash::Version::V1).0)
+            },
+            _ => Ok(Chipset::SunrisePoint),
+        }
+    }
+
+    /// True if the HAP or ME disable straps of the chipset are all set
+    pub fn high_assurance_platform(&self) -> Result<bool, String> {
+        for field in strap::me_disable(self.me_disable_chipset()?) {
+            if field.get(self.strap(field)?) == 0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Descriptor version used to decode regions. An unknown version is treated as IFDv2, as
+    /// the base and limit fields are the same, so that the regions can still be found
+    fn region_version(&self) -> flash::Version {
+        self.descriptor_version().unwrap_or(flash::Version::V2)
+    }
+
+    pub fn get_region_base_limit(&self, kind: RegionKind) -> Result<Option<(usize, usize)>, String> {
+        let version = self.region_version();
+        let frba = self.flash_region()?;
+        Ok(frba.base_limit(kind as usize, version))
+    }
+
+    /// Iterate over every populated flash region
+    pub fn regions(&self) -> Result<FlashRegions<'a>, String> {
+        let version = self.region_version();
+        let frba = self.flash_region()?;
+        Ok(FlashRegions::new(frba, version))
     }
 
     pub fn get_region(&self, kind: RegionKind) -> Result<Option<&'a [u8]>, String> {
@@ -131,6 +373,22 @@ impl<'a> Rom<'a> {
         }
     }
 
+    /// Check all regions together for overlaps, unerased gaps, and placement
+    /// outside of the image, the flash density, or 4 KiB alignment
+    pub fn validate(&self) -> Result<Vec<validate::Finding>, String> {
+        let regions: Vec<FlashRegion> = self.regions()?.collect();
+        let density = self.flash_density().ok();
+
+        let mut findings = validate::layout(&regions, self.data.len(), density);
+        findings.extend(validate::gaps(&regions, self.data));
+        Ok(findings)
+    }
+
+    /// Copy a region out of the image
+    pub fn extract_region(&self, kind: RegionKind) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.get_region(kind)?.map(|data| data.to_vec()))
+    }
+
     pub fn bios(&self) -> Result<Option<Bios<'a>>, String> {
         if let Some(data) = self.get_region(RegionKind::Bios)? {
             Ok(Some(Bios { data }))
@@ -139,30 +397,765 @@ impl<'a> Rom<'a> {
         }
     }
 
-    pub fn me(&self) -> Result<Option<Me<'a>>, String> {
-        if let Some(data) = self.get_region(RegionKind::ManagementEngine)? {
-            Ok(Some(Me { data }))
-        } else {
-            Ok(None)
+    pub fn gbe(&self) -> Result<Option<Gbe<'a>>, String> {
+        if let Some(data) = self.get_region(RegionKind::Ethernet)? {
+            Ok(Some(Gbe { data }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn ec(&self) -> Result<Option<Ec<'a>>, String> {
+        if let Some(data) = self.get_region(RegionKind::EmbeddedController)? {
+            Ok(Some(Ec { data }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn me(&self) -> Result<Option<Me<'a>>, String> {
+        if let Some(data) = self.get_region(RegionKind::ManagementEngine)? {
+            Ok(Some(Me { data }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Mutable counterpart of `Rom`, editing the descriptor in place
+pub struct RomMut<'a> {
+    data: &'a mut [u8],
+}
+
+impl<'a> RomMut<'a> {
+    pub fn new(data: &'a mut [u8]) -> Result<RomMut<'a>, String> {
+        let offset = data.len() - Rom::new(data)?.data().len();
+        Ok(RomMut {
+            data: &mut data[offset..]
+        })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        self.data
+    }
+
+    /// Parse the current state of the image
+    pub fn rom(&self) -> Result<Rom<'_>, String> {
+        Rom::new(self.data)
+    }
+
+    fn table_mut<T: plain::Plain>(&mut self, offset: usize, name: &str) -> Result<&mut T, String> {
+        if offset >= self.data.len() {
+            return Err(format!("{} table truncated", name))
+        }
+
+        plain::from_mut_bytes(&mut self.data[offset..]).map_err(|err| {
+            format!("{} table invalid: {:?}", name, err)
+        })
+    }
+
+    /// Ensure an edit left a descriptor with the same layout version
+    fn check(&self, version: flash::Version) -> Result<(), String> {
+        let new_version = self.rom()?.descriptor_version()?;
+        if new_version == version {
+            Ok(())
+        } else {
+            Err(format!("Flash descriptor version changed from {:?} to {:?}", version, new_version))
+        }
+    }
+
+    /// Replace the contents of a region. A smaller replacement is rejected unless
+    /// `pad` is set, in which case the rest of the region is filled with 0xFF
+    ///
+    /// The image is edited in place, so copy it first to produce a new image. Nothing is
+    /// written if an error is returned.
+    pub fn inject_region(&mut self, kind: RegionKind, data: &[u8], pad: bool) -> Result<(), String> {
+        let (base, limit) = self.rom()?.get_region_base_limit(kind)?.ok_or_else(|| {
+            format!("{} region not present", kind)
+        })?;
+
+        if limit >= self.data.len() {
+            return Err(format!("{} region invalid: {} >= {}", kind, limit, self.data.len()));
+        }
+
+        let size = limit + 1 - base;
+        if data.len() > size {
+            return Err(format!("{} region too small: {} > {}", kind, data.len(), size));
+        }
+        if data.len() < size && ! pad {
+            return Err(format!("{} region size mismatch: {} < {}", kind, data.len(), size));
+        }
+
+        let region = &mut self.data[base..limit + 1];
+        region[..data.len()].copy_from_slice(data);
+        for b in region[data.len()..].iter_mut() {
+            *b = 0xFF;
+        }
+
+        Ok(())
+    }
+
+    /// Move regions to new offsets, like ifdtool --newlayout. Regions that are
+    /// not listed keep their place. Contents are copied to the new location,
+    /// truncated or padded with 0xFF to fit, and freed space is erased. The
+    /// BIOS region is aligned to its end, so that the reset vector is kept.
+    pub fn relayout(&mut self, layout: &[FlashRegion]) -> Result<(), String> {
+        let (version, frba, old_regions) = {
+            let rom = self.rom()?;
+            let regions: Vec<FlashRegion> = rom.regions()?.collect();
+            (rom.descriptor_version()?, rom.flash_descriptor().frba(), regions)
+        };
+
+        let mut new_regions = old_regions.clone();
+        for region in layout {
+            if let Some(old) = new_regions.iter_mut().find(|old| old.kind == region.kind) {
+                *old = *region;
+            } else {
+                new_regions.push(*region);
+            }
+        }
+
+        for region in new_regions.iter() {
+            if region.kind as usize >= version.regions() {
+                return Err(format!("{} region not supported by {:?} descriptor", region.kind, version));
+            }
+        }
+
+        let findings = validate::layout(&new_regions, self.data.len(), None);
+        if let Some(finding) = findings.iter().find(|finding| finding.severity == validate::Severity::Error) {
+            return Err(format!("Flash layout invalid: {}", finding));
+        }
+
+        // Build the new image separately, so that nothing is changed if any step fails
+        let mut new_data = self.data.to_vec();
+
+        let moved: Vec<(FlashRegion, Option<FlashRegion>)> = new_regions.iter().filter_map(|new| {
+            let old = old_regions.iter().find(|old| old.kind == new.kind).cloned();
+            match old {
+                Some(old) if old.base == new.base && old.limit == new.limit => None,
+                _ => Some((*new, old)),
+            }
+        }).collect();
+
+        for (_new, old) in moved.iter() {
+            if let Some(old) = old {
+                for b in new_data[old.base..old.limit + 1].iter_mut() {
+                    *b = 0xFF;
+                }
+            }
+        }
+
+        for (new, old) in moved.iter() {
+            for b in new_data[new.base..new.limit + 1].iter_mut() {
+                *b = 0xFF;
+            }
+
+            if let Some(old) = old {
+                let size = cmp::min(old.size(), new.size());
+                let (src, dst) = if new.kind == RegionKind::Bios {
+                    (old.limit + 1 - size, new.limit + 1 - size)
+                } else {
+                    (old.base, new.base)
+                };
+                new_data[dst..dst + size].copy_from_slice(&self.data[src..src + size]);
+            }
+        }
+
+        {
+            let mut new_rom = RomMut { data: &mut new_data };
+            let table: &mut flash::Region = new_rom.table_mut(frba, "Flash region")?;
+            for (new, _old) in moved.iter() {
+                table.set_base_limit(new.kind as usize, Some((new.base, new.limit)), version)?;
+            }
+            new_rom.check(version)?;
+        }
+
+        self.data.copy_from_slice(&new_data);
+        Ok(())
+    }
+
+    /// Set the MAC address in every valid GbE NVM bank and fix their checksums
+    pub fn set_mac_address(&mut self, mac: [u8; 6]) -> Result<(), String> {
+        let (base, limit) = self.rom()?.get_region_base_limit(RegionKind::Ethernet)?.ok_or_else(|| {
+            format!("{} region not present", RegionKind::Ethernet)
+        })?;
+
+        if limit >= self.data.len() {
+            return Err(format!("{} region invalid: {} >= {}", RegionKind::Ethernet, limit, self.data.len()));
+        }
+
+        let mut found = false;
+        for bank in self.data[base..limit + 1].chunks_mut(gbe::BANK_SIZE) {
+            let nvm = plain::from_mut_bytes::<gbe::Nvm>(bank).map_err(|err| {
+                format!("GbE NVM invalid: {:?}", err)
+            })?;
+            if nvm.valid() {
+                nvm.set_mac_address(mac);
+                nvm.fix_checksum();
+                found = true;
+            }
+        }
+
+        if found {
+            Ok(())
+        } else {
+            Err(String::from("GbE NVM has no valid bank"))
+        }
+    }
+
+    /// Remove non-essential ME firmware, like me_cleaner
+    ///
+    /// Only FTPR and its required modules are kept. The ME region can then be shrunk, giving the
+    /// freed space to a BIOS region that directly follows it.
+    pub fn clean_me(&mut self, options: &clean::Options) -> Result<clean::Report, String> {
+        let (me, bios) = {
+            let rom = self.rom()?;
+            let regions: Vec<FlashRegion> = rom.regions()?.collect();
+            let find = |kind| regions.iter().find(|region| region.kind == kind).cloned();
+            (find(RegionKind::ManagementEngine), find(RegionKind::Bios))
+        };
+        let me = me.ok_or_else(|| format!("{} region not present", RegionKind::ManagementEngine))?;
+
+        if me.limit >= self.data.len() {
+            return Err(format!("{} region invalid: {} >= {}", me.kind, me.limit, self.data.len()));
+        }
+
+        let report = clean::clean(&mut self.data[me.base..me.limit + 1], options.relocate)?;
+
+        if options.disable {
+            self.set_high_assurance_platform(true)?;
+        }
+
+        if options.shrink && report.used < me.size() {
+            let new_me = FlashRegion {
+                limit: me.base + report.used - 1,
+                ..me
+            };
+            let mut layout = vec![new_me];
+            if let Some(bios) = bios.filter(|bios| bios.base == me.limit + 1) {
+                layout.push(FlashRegion {
+                    base: new_me.limit + 1,
+                    ..bios
+                });
+            }
+            self.relayout(&layout)?;
+        }
+
+        Ok(report)
+    }
+
+    /// Replace the microcode update for the same processor, or add it if there is none
+    ///
+    /// Updates in the microcode FFS file are repacked with their original alignment, and the FIT
+    /// microcode entries are rewritten to match, reusing unused entries if the FIT has to grow.
+    /// FIT microcode entries outside of the file are kept, unless they are for the same processor.
+    pub fn update_microcode(&mut self, update: &[u8]) -> Result<(), String> {
+        let new = microcode::Microcode::new(update)?;
+        if ! new.checksum_valid() || ! new.extended_checksum_valid() {
+            return Err(String::from("Microcode checksum invalid"));
+        }
+
+        let (bios_base, bios_len, file_offset, file_len, file_checksum, mut updates, fit_table, kept) = {
+            let rom = self.rom()?;
+            let bios = rom.bios()?.ok_or_else(|| format!("{} region not present", RegionKind::Bios))?;
+            let file = bios.microcode_file().ok_or_else(|| String::from("Microcode file not found"))?;
+            let file_offset = file.data().as_ptr() as usize - bios.data().as_ptr() as usize;
+            let file_checksum = file.header().attributes().contains(file::Attributes::ATTRIB_CHECKSUM);
+
+            let mut updates: Vec<(Option<usize>, Vec<u8>)> = Vec::new();
+            let mut replaced = false;
+            for (offset, old) in microcode::scan(file.data()) {
+                if microcode::same_processor(&old, &new) {
+                    if ! replaced {
+                        updates.push((Some(file_offset + offset), new.data().to_vec()));
+                        replaced = true;
+                    }
+                } else {
+                    updates.push((Some(file_offset + offset), old.data().to_vec()));
+                }
+            }
+            if ! replaced {
+                updates.push((None, new.data().to_vec()));
+            }
+
+            // Indexes of FIT microcode entries outside of the file
+            let mut kept = Vec::new();
+            let fit_table = match bios.fit()? {
+                Some(fit) => {
+                    for (i, item) in fit.items().iter().enumerate() {
+                        if item.entry.kind() != fit::EntryKind::Microcode {
+                            continue;
+                        }
+                        if let Some(offset) = item.offset {
+                            if offset >= file_offset && offset < file_offset + file.data().len() {
+                                continue;
+                            }
+                            if let Ok(old) = microcode::Microcode::new(&bios.data()[offset..]) {
+                                if microcode::same_processor(&old, &new) {
+                                    return Err(format!(
+                                        "FIT microcode at {:#X} is for the same processor but outside of the microcode file",
+                                        offset
+                                    ));
+                                }
+                            }
+                        }
+                        kept.push(i + 1);
+                    }
+
+                    let start = fit.offset();
+                    let len = mem::size_of_val(fit.entries());
+                    Some((start, bios.data()[start..start + len].to_vec()))
+                },
+                None => None,
+            };
+
+            let bios_base = bios.data().as_ptr() as usize - rom.data().as_ptr() as usize;
+            (bios_base, bios.data().len(), file_offset, file.data().len(), file_checksum, updates, fit_table, kept)
+        };
+
+        // Keep the largest alignment shared by the existing updates
+        let mut align = 0x1000;
+        for (offset, _) in updates.iter() {
+            if let Some(offset) = offset {
+                while align > 0x10 && offset % align != 0 {
+                    align >>= 1;
+                }
+            }
+        }
+
+        let mut offsets = Vec::new();
+        let mut offset = file_offset;
+        for (_, data) in updates.iter_mut() {
+            offset = offset.div_ceil(align) * align;
+            offsets.push(offset);
+            offset += data.len();
+        }
+        if offset > file_offset + file_len {
+            return Err(format!(
+                "Microcode file too small: {:#X} > {:#X}",
+                offset - file_offset,
+                file_len
+            ));
+        }
+
+        let bios_data = &mut self.data[bios_base..bios_base + bios_len];
+
+        let mut fit_write = None;
+        if let Some((fit_offset, table)) = fit_table {
+            let entry_size = mem::size_of::<fit::Entry>();
+            let entries: Vec<&[u8]> = table.chunks(entry_size).collect();
+            let kind = |entry: &[u8]| fit::EntryKind::from(entry[14] & 0x7F);
+
+            let version = entries.iter().find(|entry| kind(entry) == fit::EntryKind::Microcode).map_or(
+                [0x00, 0x01],
+                |entry| [entry[12], entry[13]]
+            );
+            let mut new_table: Vec<u8> = entries[0].to_vec();
+            for offset in offsets.iter() {
+                let address = (1u64 << 32) - bios_len as u64 + *offset as u64;
+                new_table.extend_from_slice(&address.to_le_bytes());
+                new_table.extend_from_slice(&[0, 0, 0, 0, version[0], version[1], 0x01, 0]);
+            }
+            for i in kept {
+                new_table.extend_from_slice(entries[i]);
+            }
+            let mut others: Vec<&[u8]> = entries[1..].iter().cloned().filter(|entry| {
+                kind(entry) != fit::EntryKind::Microcode
+            }).collect();
+            // Reclaim unused entries if the table would grow
+            let mut excess = (new_table.len() / entry_size + others.len()).saturating_sub(entries.len());
+            others.retain(|entry| {
+                if excess > 0 && kind(entry) == fit::EntryKind::Unused {
+                    excess -= 1;
+                    false
+                } else {
+                    true
+                }
+            });
+            for entry in others {
+                new_table.extend_from_slice(entry);
+            }
+            // Shrinking the table would leave stale entries, so pad with unused entries instead
+            while new_table.len() < table.len() {
+                new_table.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x7F, 0]);
+            }
+
+            let end = fit_offset + new_table.len();
+            if new_table.len() > table.len() {
+                let erased = bios_data.get(fit_offset + table.len()..end).is_some_and(|extra| {
+                    extra.iter().all(|&b| b == 0xFF)
+                });
+                if ! erased {
+                    return Err(String::from("FIT has no room for more microcode entries"));
+                }
+            }
+
+            let count = (new_table.len() / entry_size) as u32;
+            new_table[8..11].copy_from_slice(&count.to_le_bytes()[..3]);
+            if new_table[14] & 0x80 != 0 {
+                new_table[15] = 0;
+                let sum = new_table.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+                new_table[15] = sum.wrapping_neg();
+            }
+            fit_write = Some((fit_offset, new_table));
+        }
+
+        for b in bios_data[file_offset..file_offset + file_len].iter_mut() {
+            *b = 0xFF;
+        }
+        for ((_, data), offset) in updates.iter().zip(offsets.iter()) {
+            bios_data[*offset..*offset + data.len()].copy_from_slice(data);
+        }
+
+        if file_checksum {
+            let sum = bios_data[file_offset..file_offset + file_len].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+            // File checksum is the high byte of the integrity check
+            bios_data[file_offset - mem::size_of::<file::Header>() + 17] = sum.wrapping_neg();
+        }
+
+        if let Some((fit_offset, new_table)) = fit_write {
+            bios_data[fit_offset..fit_offset + new_table.len()].copy_from_slice(&new_table);
+        }
+
+        Ok(())
+    }
+
+    /// Set a soft strap field
+    pub fn set_strap(&mut self, field: &strap::StrapField, value: u32) -> Result<(), String> {
+        let (version, old, offset) = {
+            let rom = self.rom()?;
+            let descriptor = rom.flash_descriptor();
+            let offset = match field.table {
+                strap::StrapTable::Pch => descriptor.fpsba(),
+                strap::StrapTable::Processor => descriptor.fmsba(),
+            };
+            (rom.descriptor_version()?, rom.strap(field)?, offset + field.index * 4)
+        };
+
+        let strap: &mut u32 = self.table_mut(offset, field.name)?;
+        *strap = field.set(old, value);
+
+        self.check(version)
+    }
+
+    /// Set or clear the HAP or ME disable straps, depending on the chipset
+    pub fn set_high_assurance_platform(&mut self, enable: bool) -> Result<(), String> {
+        let fields = {
+            let rom = self.rom()?;
+            let fields = strap::me_disable(rom.me_disable_chipset()?);
+            // Check that every strap is present before changing any of them
+            for field in fields {
+                rom.strap(field)?;
+            }
+            fields
+        };
+
+        for field in fields {
+            self.set_strap(field, enable as u32)?;
+        }
+        Ok(())
+    }
+
+    /// Replace the permissions of a master
+    pub fn set_master_access(&mut self, access: &flash::MasterAccess) -> Result<(), String> {
+        let (version, offset) = {
+            let rom = self.rom()?;
+            (rom.descriptor_version()?, rom.flash_descriptor().fmba())
+        };
+
+        if ! flash::MasterKind::all(version).contains(&access.kind) {
+            return Err(format!("{} master not present in {:?} descriptor", access.kind, version));
+        }
+
+        let master: &mut flash::Master = self.table_mut(offset, "Flash master")?;
+        let value = access.value(master.value(access.kind), version);
+        master.set_value(access.kind, value);
+
+        self.check(version)
+    }
+
+    /// Restrict masters to their own regions, like ifdtool --lock
+    pub fn lock(&mut self) -> Result<(), String> {
+        let (version, masters, present) = {
+            let rom = self.rom()?;
+            let present: Vec<RegionKind> = rom.regions()?.map(|region| region.kind).collect();
+            (rom.descriptor_version()?, rom.master_access()?, present)
+        };
+
+        for mut access in masters {
+            use flash::MasterKind as M;
+            use flash::Version as V;
+            use RegionKind as R;
+
+            let grants: &[(RegionKind, bool)] = match (access.kind, version) {
+                (M::Host, V::V1) => &[(R::Descriptor, false), (R::Bios, true), (R::Ethernet, true)],
+                (M::Host, V::V2) => &[
+                    (R::Descriptor, false),
+                    (R::Bios, true),
+                    (R::Ethernet, true),
+                    (R::PlatformData, true),
+                    (R::EmbeddedController, false),
+                ],
+                (M::ManagementEngine, V::V1) => &[
+                    (R::Descriptor, false),
+                    (R::ManagementEngine, true),
+                    (R::Ethernet, true),
+                ],
+                (M::ManagementEngine, V::V2) => &[
+                    (R::Descriptor, false),
+                    (R::ManagementEngine, true),
+                    (R::Ethernet, false),
+                ],
+                (M::Ethernet, V::V1) => &[(R::Ethernet, true)],
+                (M::Ethernet, V::V2) => &[(R::Descriptor, false), (R::Ethernet, true)],
+                (M::EmbeddedController, _) => &[(R::Descriptor, false), (R::EmbeddedController, true)],
+                (M::Reserved, _) => &[],
+            };
+
+            // IFDv2 masters only get access to regions that are present, and
+            // the GbE and EC masters get nothing if their region is missing
+            let own = match access.kind {
+                M::Ethernet => Some(R::Ethernet),
+                M::EmbeddedController => Some(R::EmbeddedController),
+                _ => None,
+            };
+            let skip = |region: RegionKind| {
+                version == V::V2 && (
+                    ! present.contains(&region) ||
+                    own.is_some_and(|own| ! present.contains(&own))
+                )
+            };
+
+            access.read = 0;
+            access.write = 0;
+            for &(region, write) in grants {
+                if ! skip(region) {
+                    access.set_read(region, true);
+                    access.set_write(region, write);
+                }
+            }
+
+            self.set_master_access(&access)?;
+        }
+
+        Ok(())
+    }
+
+    /// Allow masters to access all regions, like ifdtool --unlock
+    pub fn unlock(&mut self) -> Result<(), String> {
+        let (version, masters) = {
+            let rom = self.rom()?;
+            (rom.descriptor_version()?, rom.master_access()?)
+        };
+
+        let all = match version {
+            flash::Version::V1 => 0xff,
+            flash::Version::V2 => 0xfff,
+        };
+
+        for mut access in masters {
+            match (access.kind, version) {
+                (flash::MasterKind::Reserved, _) => continue,
+                (flash::MasterKind::Ethernet, flash::Version::V1) => {
+                    access.read = 1 << RegionKind::Ethernet as usize;
+                    access.write = 1 << RegionKind::Ethernet as usize;
+                },
+                _ => {
+                    access.read = all;
+                    access.write = all;
+                },
+            }
+
+            self.set_master_access(&access)?;
+        }
+
+        Ok(())
+    }
+
+    /// Set the fast read, write/erase and read ID/status clocks, like ifdtool --spifreq
+    pub fn set_spi_frequency(&mut self, frequency: flash::SpiFrequency) -> Result<(), String> {
+        let (version, offset) = {
+            let rom = self.rom()?;
+            (rom.descriptor_version()?, rom.flash_descriptor().fcba())
+        };
+
+        let value = frequency.value(version).ok_or_else(|| {
+            format!("SPI frequency {} not supported by {:?} descriptor", frequency, version)
+        })? as u32;
+
+        let component: &mut flash::Component = self.table_mut(offset, "Flash component")?;
+        let comp = component.comp & !0x3fe0_0000;
+        component.comp = comp | value << 21 | value << 24 | value << 27;
+
+        self.check(version)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FlashRegion {
+    pub kind: RegionKind,
+    pub base: usize,
+    pub limit: usize,
+}
+
+impl FlashRegion {
+    pub fn size(&self) -> usize {
+        self.limit + 1 - self.base
+    }
+}
+
+pub struct FlashRegions<'a> {
+    table: &'a flash::Region,
+    version: flash::Version,
+    i: usize,
+}
+
+impl<'a> FlashRegions<'a> {
+    pub fn new(table: &'a flash::Region, version: flash::Version) -> Self {
+        Self {
+            table,
+            version,
+            i: 0
+        }
+    }
+}
+
+impl<'a> Iterator for FlashRegions<'a> {
+    type Item = FlashRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.i < self.version.regions() {
+            let kind = RegionKind::from_index(self.i)?;
+            self.i += 1;
+
+            if let Some((base, limit)) = self.table.base_limit(kind as usize, self.version) {
+                return Some(FlashRegion { kind, base, limit });
+            }
+        }
+
+        None
+    }
+}
+
+pub struct Bios<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Bios<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Bios, String> {
+        Ok(Bios { data })
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn volumes(&self) -> BiosVolumes {
+        BiosVolumes::new(self.data)
+    }
+
+    /// Startup ACM referenced by the FIT
+    pub fn startup_acm(&self) -> Result<Option<acm::Acm<'a>>, String> {
+        let fit = match self.fit()? {
+            Some(fit) => fit,
+            None => return Ok(None),
+        };
+        for item in fit.items() {
+            if item.entry.kind() == fit::EntryKind::StartupAcm {
+                let address = item.entry.address;
+                let offset = item.offset.ok_or_else(|| {
+                    format!("FIT startup ACM {:#X} outside of BIOS region", address)
+                })?;
+                return acm::Acm::new(&self.data[offset..]).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// True if the startup ACM supports every processor with microcode in the image
+    pub fn startup_acm_matches_microcode(&self) -> Result<Option<bool>, String> {
+        let acm = match self.startup_acm()? {
+            Some(acm) => acm,
+            None => return Ok(None),
+        };
+        for (_, update) in self.microcode()? {
+            let signature = update.signature().0;
+            // Each processor flag bit is a platform ID, found in bits 52:50 of MSR 0x17
+            let matches = (0..8u64).filter(|bit| update.processor_flags() & (1 << bit) != 0).any(|bit| {
+                acm.matches_processor(signature, bit << 50).unwrap_or(false)
+            });
+            if ! matches {
+                return Ok(Some(false));
+            }
+        }
+        Ok(Some(true))
+    }
+
+    /// True if the startup ACM supports the chipset with these TXT vendor, device and revision IDs
+    pub fn startup_acm_matches_chipset(&self, vendor_id: u16, device_id: u16, revision_id: u16) -> Result<Option<bool>, String> {
+        match self.startup_acm()? {
+            Some(acm) => acm.matches_chipset(vendor_id, device_id, revision_id).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Boot Guard 1.0 Key Manifest and Boot Policy Manifest
+    pub fn boot_guard(&self) -> Result<Option<bootguard::BootGuard<'a>>, String> {
+        bootguard::BootGuard::new(self.data)
+    }
+
+    /// Microcode updates referenced by the FIT, or found in the microcode file or by scanning if
+    /// there is no FIT
+    pub fn microcode(&self) -> Result<Vec<(usize, microcode::Microcode<'a>)>, String> {
+        if let Some(fit) = self.fit()? {
+            let mut updates = Vec::new();
+            for item in fit.items() {
+                if item.entry.kind() != fit::EntryKind::Microcode {
+                    continue;
+                }
+                let address = item.entry.address;
+                let offset = item.offset.ok_or_else(|| {
+                    format!("FIT microcode {:#X} outside of BIOS region", address)
+                })?;
+                // Slots reserved for updates added later are left erased
+                if self.data[offset..].iter().take(mem::size_of::<microcode::Header>()).all(|&b| b == 0xFF) {
+                    continue;
+                }
+                updates.push((offset, microcode::Microcode::new(&self.data[offset..])?));
+            }
+            if ! updates.is_empty() {
+                return Ok(updates);
+            }
         }
-    }
-}
 
-pub struct Bios<'a> {
-    data: &'a [u8],
-}
+        if let Some(file) = self.microcode_file() {
+            let offset = file.data().as_ptr() as usize - self.data.as_ptr() as usize;
+            return Ok(microcode::scan(file.data()).into_iter().map(|(i, update)| (offset + i, update)).collect());
+        }
 
-impl<'a> Bios<'a> {
-    pub fn new(data: &'a [u8]) -> Result<Bios, String> {
-        Ok(Bios { data })
+        Ok(microcode::scan(self.data))
     }
 
-    pub fn data(&self) -> &'a [u8] {
-        self.data
+    /// FFS file holding the microcode updates
+    pub fn microcode_file(&self) -> Option<BiosFile<'a>> {
+        for volume in BiosVolumes::new(self.data) {
+            for file in BiosFiles::new(volume.data()) {
+                let guid = file.header().guid;
+                if guid == microcode::FILE_GUID || guid == microcode::AMI_FILE_GUID {
+                    return Some(file);
+                }
+            }
+        }
+        None
     }
 
-    pub fn volumes(&self) -> BiosVolumes {
-        BiosVolumes::new(self.data)
+    /// Firmware Interface Table, found through the pointer at 4 GiB - 0x40
+    pub fn fit(&self) -> Result<Option<fit::Fit<'a>>, String> {
+        fit::Fit::new(self.data)
     }
 }
 
@@ -189,28 +1182,15 @@ impl<'a> Iterator for BiosVolumes<'a> {
             let header = plain::from_bytes::<volume::Header>(header_data).unwrap();
 
             if header.valid() {
-                self.i += header.length as usize;
-
-                /*
-                self.i += mem::size_of::<volume::Header>();
-
-                while self.i + mem::size_of::<volume::BlockEntry>() <= self.data.len() {
-                    let block_entry = plain::from_bytes::<volume::BlockEntry>(&self.data[self.i..]).unwrap();
-                    self.i += mem::size_of::<volume::BlockEntry>();
-
-                    if block_entry.num_blocks == 0 && block_entry.block_length == 0 {
-                        break;
-                    } else {
-                        println!("    {}, {}", block_entry.num_blocks, block_entry.block_length);
-                    }
-                }
-
-                self.i += header.header_length as usize - mem::size_of::<volume::Header>();
-                */
+                // Invalid lengths are reported by `BiosVolume::validate` instead of skipping the volume
+                let header_length = header.header_length as usize;
+                let length = cmp::min(header.length, header_data.len() as u64) as usize;
+                self.i += cmp::max(length, 8);
 
                 return Some(BiosVolume {
                     header,
-                    data: &header_data[header.header_length as usize .. header.length as usize]
+                    raw: &header_data[..length],
+                    data: &header_data[cmp::min(header_length, length)..length],
                 });
             } else {
                 self.i += 8;
@@ -223,6 +1203,7 @@ impl<'a> Iterator for BiosVolumes<'a> {
 
 pub struct BiosVolume<'a> {
     header: &'a volume::Header,
+    raw: &'a [u8],
     data: &'a [u8],
 }
 
@@ -238,6 +1219,55 @@ impl<'a> BiosVolume<'a> {
     pub fn files(&self) -> BiosFiles {
         BiosFiles::new(self.data)
     }
+
+    /// Block map entries following the header, without the terminating entry
+    pub fn block_map(&self) -> Result<&'a [volume::BlockEntry], volume::Error> {
+        let header_length = self.header.header_length;
+        let start = mem::size_of::<volume::Header>();
+        let end = header_length as usize;
+        if end < start || end > self.raw.len() {
+            return Err(volume::Error::HeaderLength(header_length));
+        }
+
+        let entries = plain::slice_from_bytes::<volume::BlockEntry>(&self.raw[start..end]).unwrap_or(&[]);
+        match entries.iter().position(|entry| entry.num_blocks == 0 && entry.block_length == 0) {
+            Some(count) => Ok(&entries[..count]),
+            None => Err(volume::Error::BlockMapUnterminated),
+        }
+    }
+
+    /// Check the header checksum, header length and block map against the volume length
+    pub fn validate(&self) -> Result<(), volume::Error> {
+        let length = self.header.length;
+        if length > self.raw.len() as u64 {
+            return Err(volume::Error::Truncated {
+                length,
+                available: self.raw.len(),
+            });
+        }
+
+        let header_length = self.header.header_length;
+        if header_length & 1 != 0 || header_length as u64 > length {
+            return Err(volume::Error::HeaderLength(header_length));
+        }
+
+        let blocks = self.block_map()?.iter().fold(0u64, |total, entry| {
+            total + entry.num_blocks as u64 * entry.block_length as u64
+        });
+
+        let sum = self.raw[..header_length as usize].chunks(2).fold(0u16, |sum, word| {
+            sum.wrapping_add(word[0] as u16 | (word[1] as u16) << 8)
+        });
+        if sum != 0 {
+            return Err(volume::Error::Checksum(sum));
+        }
+
+        if blocks != length {
+            return Err(volume::Error::BlockMapLength { blocks, length });
+        }
+
+        Ok(())
+    }
 }
 
 pub struct BiosFiles<'a> {
@@ -262,15 +1292,17 @@ impl<'a> Iterator for BiosFiles<'a> {
             let header_data = &self.data[self.i..];
             let header = plain::from_bytes::<file::Header>(header_data).unwrap();
 
-            if header.size() == 0xFFFFFF {
+            // Stop at free space, or at a file that does not fit in the volume
+            let size = header.size();
+            if size == 0xFFFFFF || size < mem::size_of::<file::Header>() || size > header_data.len() {
                 self.i = self.data.len();
                 None
             } else {
-                self.i += ((header.size() + 7) / 8) * 8;
+                self.i += size.div_ceil(8) * 8;
 
                 Some(BiosFile {
                     header,
-                    data: &header_data[mem::size_of::<file::Header>() .. header.size()]
+                    data: &header_data[mem::size_of::<file::Header>() .. size]
                 })
             }
         } else {
@@ -353,6 +1385,104 @@ impl<'a> BiosSection<'a> {
     }
 }
 
+pub struct Gbe<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Gbe<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Gbe<'a>, String> {
+        Ok(Gbe { data })
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Offset and contents of the first valid NVM bank
+    pub fn nvm(&self) -> Result<(usize, &'a gbe::Nvm), String> {
+        for (i, bank) in self.data.chunks(gbe::BANK_SIZE).enumerate() {
+            let nvm = plain::from_bytes::<gbe::Nvm>(bank).map_err(|err| {
+                format!("GbE NVM invalid: {:?}", err)
+            })?;
+            if nvm.valid() {
+                return Ok((i * gbe::BANK_SIZE, nvm));
+            }
+        }
+
+        Err(String::from("GbE NVM has no valid bank"))
+    }
+
+    pub fn mac_address(&self) -> Result<[u8; 6], String> {
+        Ok(self.nvm()?.1.mac_address())
+    }
+
+    /// Image version as major, minor and build
+    pub fn version(&self) -> Result<(u8, u8, u8), String> {
+        Ok(self.nvm()?.1.version())
+    }
+
+    pub fn checksum_valid(&self) -> Result<bool, String> {
+        Ok(self.nvm()?.1.checksum_valid())
+    }
+
+    /// PHY register writes from the extended configuration area
+    pub fn phy_config(&self) -> Result<Vec<gbe::PhyConfig>, String> {
+        let (offset, nvm) = self.nvm()?;
+        let (pointer, length) = nvm.phy_config_area();
+
+        let start = offset + pointer * 4;
+        let end = start + length * 4;
+        if end > offset + gbe::BANK_SIZE || end > self.data.len() {
+            return Err(format!("GbE PHY configuration invalid: {:#X} - {:#X}", start, end));
+        }
+
+        Ok(self.data[start..end].chunks(4).map(|entry| gbe::PhyConfig {
+            data: entry[0] as u16 | (entry[1] as u16) << 8,
+            address: entry[2] as u16 | (entry[3] as u16) << 8,
+        }).collect())
+    }
+}
+
+pub struct Ec<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Ec<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Ec<'a>, String> {
+        Ok(Ec { data })
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// True if the region is erased or zeroed
+    pub fn is_empty(&self) -> bool {
+        self.data.iter().all(|&b| b == 0xFF) || self.data.iter().all(|&b| b == 0x00)
+    }
+
+    /// Size of the firmware image, without trailing erased bytes
+    pub fn size(&self) -> usize {
+        self.data.iter().rposition(|&b| b != 0xFF).map_or(0, |i| i + 1)
+    }
+
+    /// System76 EC board string, such as `system76/lemp9`
+    pub fn board(&self) -> Option<&'a str> {
+        self.identifier(b"76EC_BOARD=")
+    }
+
+    /// System76 EC firmware version string
+    pub fn version(&self) -> Option<&'a str> {
+        self.identifier(b"76EC_VERSION=")
+    }
+
+    fn identifier(&self, prefix: &[u8]) -> Option<&'a str> {
+        let start = self.data.windows(prefix.len()).position(|window| window == prefix)? + prefix.len();
+        let len = self.data[start..].iter().position(|&b| b == 0)?;
+        core::str::from_utf8(&self.data[start..start + len]).ok()
+    }
+}
+
 pub struct Me<'a> {
     data: &'a [u8],
 }
@@ -366,7 +1496,112 @@ impl<'a> Me<'a> {
         self.data
     }
 
+    /// Manifest of the FTPR code partition
+    pub fn manifest(&self) -> Result<Option<manifest::Manifest<'a>>, String> {
+        if let Ok(Some(cpd)) = self.cpd("FTPR") {
+            if let Some(data) = cpd.entry("FTPR.man").and_then(|entry| cpd.entry_data(entry)) {
+                return manifest::Manifest::new(data).map(Some);
+            }
+        }
+
+        if let Ok(fpt) = self.fpt() {
+            if let Some(data) = fpt.entry("FTPR").and_then(|entry| fpt.partition(entry)) {
+                return manifest::Manifest::new(data).map(Some);
+            }
+        }
+
+        // Images without a partition table, search for the manifest tag
+        let mut i = 0x1C;
+        while i + 4 <= self.data.len() {
+            if self.data[i..i + 4] == *b"$MN2" || self.data[i..i + 4] == *b"$MAN" {
+                if let Ok(manifest) = manifest::Manifest::new(&self.data[i - 0x1C..]) {
+                    return Ok(Some(manifest));
+                }
+            }
+            i += 4;
+        }
+
+        Ok(None)
+    }
+
+    /// Decompressed contents of a module from any code partition
+    ///
+    /// Modules using Huffman compression, as in ME 6-10, return an error.
+    pub fn module(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+        for cpd in self.cpds()? {
+            if let Some(module) = cpd.modules().iter().find(|module| module.name == name) {
+                return module.decompress().map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// ME File System from the MFS partition, or the MFSB partition if there is none
+    pub fn mfs(&self) -> Result<Option<mfs::Mfs<'a>>, String> {
+        let fpt = self.fpt()?;
+        match ["MFS", "MFSB"].iter().find_map(|name| fpt.entry(name).and_then(|entry| fpt.partition(entry))) {
+            Some(data) => mfs::Mfs::new(data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Configuration file from the ME File System, such as `mfs::INTEL_CFG` or `mfs::FITC_CFG`
+    pub fn config(&self, index: u16) -> Result<Option<mfs::Config>, String> {
+        match self.mfs()? {
+            Some(mfs) => mfs.config(index),
+            None => Ok(None),
+        }
+    }
+
+    /// Identify the kind of engine firmware
+    pub fn family(&self) -> Result<family::Family, String> {
+        let fpt = self.fpt().ok();
+        let partitions: Vec<&str> = fpt.iter().flat_map(|fpt| fpt.entries()).map(|entry| entry.name()).collect();
+        let manifest = self.manifest()?;
+        family::Family::new(&partitions, manifest.as_ref(), self.data.len()).ok_or_else(|| {
+            String::from("ME firmware family unknown")
+        })
+    }
+
+    /// Evidence for the firmware SKU from the manifests of the code partitions
+    pub fn sku_hints(&self) -> Result<Vec<family::SkuHint<'a>>, String> {
+        let mut hints = Vec::new();
+
+        if let Some(manifest) = self.manifest()? {
+            if let Some(sku) = manifest.sku_attributes().and_then(family::Sku::from_attributes) {
+                hints.push(family::SkuHint::Attributes(sku));
+            }
+        }
+
+        // ME 6 to 10 list the modules of each code partition in its manifest
+        if let Ok(fpt) = self.fpt() {
+            for entry in fpt.entries() {
+                let manifest = match fpt.partition(entry).map(manifest::Manifest::new) {
+                    Some(Ok(manifest)) => manifest,
+                    _ => continue,
+                };
+                for header in manifest.module_headers().unwrap_or_default() {
+                    if header.name().starts_with("AMT") {
+                        hints.push(family::SkuHint::AmtModule(header.name()));
+                    }
+                }
+            }
+        }
+
+        Ok(hints)
+    }
+
+    /// SKU suggested by the hints, assuming consumer firmware if there are none
+    pub fn sku(&self) -> Result<family::Sku, String> {
+        Ok(self.sku_hints()?.first().map_or(family::Sku::Consumer, |hint| hint.sku()))
+    }
+
+    /// Firmware version from the manifest, or the FITC version in the `$FPT` if there is none
     pub fn version(&self) -> Option<String> {
+        if let Ok(Some(manifest)) = self.manifest() {
+            return Some(format!("{}", manifest.version()));
+        }
+
         let mut i = 0;
         while i + 4 <= self.data.len() {
             if &self.data[i..i + 4] == b"$FPT" {
@@ -392,12 +1627,699 @@ impl<'a> Me<'a> {
         }
     }
 
+    /// Flash Partition Table of the ME region
+    pub fn fpt(&self) -> Result<fpt::Fpt<'a>, String> {
+        fpt::Fpt::new(self.data)
+    }
+
+    /// Code Partition Directory of the named partition, such as `FTPR`
+    pub fn cpd(&self, name: &str) -> Result<Option<cpd::Cpd<'a>>, String> {
+        let fpt = self.fpt()?;
+        match fpt.entry(name).and_then(|entry| fpt.partition(entry)) {
+            Some(data) => cpd::Cpd::new(data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Code Partition Directories of all code partitions, such as FTPR, NFTP and IUNP
+    pub fn cpds(&self) -> Result<Vec<cpd::Cpd<'a>>, String> {
+        let fpt = self.fpt()?;
+        let mut cpds = Vec::new();
+        for entry in fpt.entries() {
+            if let Some(data) = fpt.partition(entry) {
+                if data.starts_with(b"$CPD") {
+                    cpds.push(cpd::Cpd::new(data)?);
+                }
+            }
+        }
+        Ok(cpds)
+    }
+
+    /// Number of partitions in the Flash Partition Table
     pub fn modules(&self) -> Option<u32> {
-        if self.data.len() >= 0x18 {
-            let bytes = &self.data[0x14..0x18];
-            Some(bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24)
-        } else {
-            None
+        self.fpt().ok().map(|fpt| fpt.header().num_entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use uefi::guid::Guid;
+
+    use super::*;
+
+    fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// 16 KiB image with a descriptor and a BIOS region, using the given read clock
+    fn image(read_clock: u32) -> Vec<u8> {
+        let mut data = vec![0xFF; 0x4000];
+        for b in data[..0x1000].iter_mut() {
+            *b = 0;
+        }
+        write_u32(&mut data, 0x10, 0x0FF0A55A);
+        // FCBA 0x30, FRBA 0x40
+        write_u32(&mut data, 0x14, 0x0004_0003);
+        write_u32(&mut data, 0x30, read_clock << 17);
+        write_u32(&mut data, 0x40, 0x0000_0000);
+        write_u32(&mut data, 0x44, 0x0003_0001);
+        for i in 2..16 {
+            write_u32(&mut data, 0x40 + i * 4, 0x0000_7FFF);
+        }
+        data
+    }
+
+    /// Point FMBA at 0x80 and write the master values there
+    fn write_masters(data: &mut [u8], values: &[u32]) {
+        data[0x18] = 0x08;
+        for (i, &value) in values.iter().enumerate() {
+            write_u32(data, 0x80 + i * 4, value);
+        }
+    }
+
+    /// Microcode update with 0x10 bytes of data and a valid checksum
+    fn microcode_update(signature: u32, revision: u32) -> Vec<u8> {
+        let mut data = vec![0; 0x40];
+        write_u32(&mut data, 0x00, 1);
+        write_u32(&mut data, 0x04, revision);
+        write_u32(&mut data, 0x0C, signature);
+        write_u32(&mut data, 0x14, 1);
+        write_u32(&mut data, 0x18, 1);
+        write_u32(&mut data, 0x1C, 0x10);
+        write_u32(&mut data, 0x20, 0x40);
+        let sum = data.chunks(4).fold(0u32, |sum, dword| {
+            sum.wrapping_add(u32::from_le_bytes([dword[0], dword[1], dword[2], dword[3]]))
+        });
+        write_u32(&mut data, 0x10, sum.wrapping_neg());
+        data
+    }
+
+    /// Write a FIT at `offset` in a BIOS region, with an entry of each kind for each target offset
+    fn write_fit(bios: &mut [u8], offset: usize, entries: &[(u8, usize)]) {
+        let base = (1u64 << 32) - bios.len() as u64;
+        let len = bios.len();
+        bios[len - fit::POINTER_OFFSET..][..8].copy_from_slice(&(base + offset as u64).to_le_bytes());
+
+        let mut table = fit::SIGNATURE.to_le_bytes().to_vec();
+        table.extend_from_slice(&[entries.len() as u8 + 1, 0, 0, 0, 0x00, 0x01, 0x00, 0x00]);
+        for &(kind, target) in entries {
+            table.extend_from_slice(&(base + target as u64).to_le_bytes());
+            table.extend_from_slice(&[0, 0, 0, 0, 0x00, 0x01, kind, 0x00]);
+        }
+        bios[offset..offset + table.len()].copy_from_slice(&table);
+    }
+
+    #[test]
+    fn microcode_erased_fit_slot() {
+        let mut data = vec![0xFF; 0x4000];
+        let update = microcode_update(0x906EA, 0xF0);
+        data[0x1000..0x1040].copy_from_slice(&update);
+        write_fit(&mut data, 0x3000, &[(0x01, 0x1000), (0x01, 0x2000)]);
+
+        let bios = Bios::new(&data).unwrap();
+        let updates = bios.microcode().unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].0, 0x1000);
+        assert_eq!(updates[0].1.revision(), 0xF0);
+    }
+
+    fn write_guid(data: &mut [u8], offset: usize, guid: Guid) {
+        data[offset..offset + 4].copy_from_slice(&guid.0.to_le_bytes());
+        data[offset + 4..offset + 6].copy_from_slice(&guid.1.to_le_bytes());
+        data[offset + 6..offset + 8].copy_from_slice(&guid.2.to_le_bytes());
+        data[offset + 8..offset + 16].copy_from_slice(&guid.3);
+    }
+
+    /// Image with a microcode file holding updates for 906EA and 806EC, and a FIT that also lists
+    /// an update for A0655 outside of the file
+    fn microcode_image() -> Vec<u8> {
+        let mut data = image(0);
+        let bios = &mut data[0x1000..];
+
+        // Firmware volume at 0, holding the microcode file at 0x48 with data at 0x60
+        bios[..0x48].iter_mut().for_each(|b| *b = 0);
+        bios[0x20..0x28].copy_from_slice(&0x2000u64.to_le_bytes());
+        bios[0x28..0x2C].copy_from_slice(b"_FVH");
+        bios[0x30..0x32].copy_from_slice(&0x48u16.to_le_bytes());
+        write_guid(bios, 0x48, microcode::FILE_GUID);
+        bios[0x58..0x60].copy_from_slice(&[0, 0, 0x01, 0, 0x18, 0x01, 0, 0xF8]);
+        bios[0x60..0xA0].copy_from_slice(&microcode_update(0x906EA, 1));
+        bios[0xA0..0xE0].copy_from_slice(&microcode_update(0x806EC, 2));
+
+        bios[0x2000..0x2040].copy_from_slice(&microcode_update(0xA0655, 3));
+        write_fit(bios, 0x2800, &[(0x01, 0x60), (0x01, 0xA0), (0x01, 0x2000), (0x02, 0x2400), (0x7F, 0)]);
+        // Use the FIT checksum
+        bios[0x2800 + 14] = 0x80;
+        data
+    }
+
+    fn microcode_revisions(data: &[u8]) -> Vec<(usize, u32, u32)> {
+        let bios = Bios::new(&data[0x1000..]).unwrap();
+        bios.microcode().unwrap().iter().map(|(offset, update)| {
+            (*offset, update.signature().0, update.revision())
+        }).collect()
+    }
+
+    #[test]
+    fn microcode_file_ami() {
+        let mut data = microcode_image();
+        let bios = Bios::new(&data[0x1000..]).unwrap();
+        assert_eq!(bios.microcode_file().map(|file| file.data().len()), Some(0x100));
+
+        write_guid(&mut data, 0x1048, microcode::AMI_FILE_GUID);
+        let bios = Bios::new(&data[0x1000..]).unwrap();
+        let file = bios.microcode_file().unwrap();
+        assert_eq!(file.data().as_ptr() as usize - data.as_ptr() as usize, 0x1060);
+
+        write_guid(&mut data, 0x1048, Guid(0, 0, 0, [0; 8]));
+        assert!(Bios::new(&data[0x1000..]).unwrap().microcode_file().is_none());
+    }
+
+    #[test]
+    fn bios_files_truncated() {
+        let mut data = vec![0xFF; 0x80];
+        data[..0x18].iter_mut().for_each(|b| *b = 0);
+        data[0x14..0x17].copy_from_slice(&[0x20, 0x00, 0x00]);
+        data[0x20..0x38].iter_mut().for_each(|b| *b = 0);
+
+        // File past the end of the volume
+        data[0x34..0x37].copy_from_slice(&[0x61, 0x00, 0x00]);
+        let sizes: Vec<usize> = BiosFiles::new(&data).map(|file| file.data().len()).collect();
+        assert_eq!(sizes, vec![0x08]);
+
+        // File smaller than its header
+        data[0x34..0x37].copy_from_slice(&[0x17, 0x00, 0x00]);
+        assert_eq!(BiosFiles::new(&data).count(), 1);
+
+        // File ending exactly at the end of the volume
+        data[0x34..0x37].copy_from_slice(&[0x60, 0x00, 0x00]);
+        let sizes: Vec<usize> = BiosFiles::new(&data).map(|file| file.data().len()).collect();
+        assert_eq!(sizes, vec![0x08, 0x48]);
+        assert_eq!(BiosFiles::new(&data[..0x30]).count(), 1);
+    }
+
+    /// Firmware volume of one 4 KiB block with a valid header checksum
+    fn firmware_volume() -> Vec<u8> {
+        let mut data = vec![0xFF; 0x1000];
+        data[..0x48].iter_mut().for_each(|b| *b = 0);
+        data[0x20..0x28].copy_from_slice(&0x1000u64.to_le_bytes());
+        data[0x28..0x2C].copy_from_slice(b"_FVH");
+        data[0x30..0x32].copy_from_slice(&0x48u16.to_le_bytes());
+        data[0x38..0x40].copy_from_slice(&[0x01, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00]);
+        fix_volume_checksum(&mut data);
+        data
+    }
+
+    fn fix_volume_checksum(data: &mut [u8]) {
+        data[0x32..0x34].copy_from_slice(&[0, 0]);
+        let header_length = (data[0x30] as usize | (data[0x31] as usize) << 8).min(data.len()) & !1;
+        let sum = data[..header_length].chunks(2).fold(0u16, |sum, word| {
+            sum.wrapping_add(word[0] as u16 | (word[1] as u16) << 8)
+        });
+        data[0x32..0x34].copy_from_slice(&sum.wrapping_neg().to_le_bytes());
+    }
+
+    fn validate_volume(data: &[u8]) -> Result<(), volume::Error> {
+        BiosVolumes::new(data).next().unwrap().validate()
+    }
+
+    #[test]
+    fn volume_valid() {
+        let data = firmware_volume();
+        let volume = BiosVolumes::new(&data).next().unwrap();
+        let blocks: Vec<(u32, u32)> = volume.block_map().unwrap().iter().map(|entry| {
+            (entry.num_blocks, entry.block_length)
+        }).collect();
+        assert_eq!(blocks, vec![(1, 0x1000)]);
+        assert_eq!(volume.data().len(), 0x1000 - 0x48);
+        assert_eq!(volume.validate(), Ok(()));
+    }
+
+    #[test]
+    fn volume_checksum() {
+        let mut data = firmware_volume();
+        data[0x34] = 0x01;
+        assert_eq!(validate_volume(&data), Err(volume::Error::Checksum(0x0001)));
+        assert_eq!(volume::Error::Checksum(0x0001).to_string(), "header checksum invalid: sum 0x0001");
+    }
+
+    #[test]
+    fn volume_header_length() {
+        // Odd
+        let mut data = firmware_volume();
+        data[0x30] = 0x49;
+        assert_eq!(validate_volume(&data), Err(volume::Error::HeaderLength(0x49)));
+
+        // Past the end of the volume
+        data[0x30..0x32].copy_from_slice(&0x2000u16.to_le_bytes());
+        assert_eq!(validate_volume(&data), Err(volume::Error::HeaderLength(0x2000)));
+
+        // Too small to hold the block map
+        data[0x30..0x32].copy_from_slice(&0x30u16.to_le_bytes());
+        let volume = BiosVolumes::new(&data).next().unwrap();
+        assert_eq!(volume.block_map().err(), Some(volume::Error::HeaderLength(0x30)));
+        assert_eq!(volume.validate(), Err(volume::Error::HeaderLength(0x30)));
+    }
+
+    #[test]
+    fn volume_block_map_unterminated() {
+        let mut data = firmware_volume();
+        data[0x30] = 0x40;
+        fix_volume_checksum(&mut data);
+        assert_eq!(validate_volume(&data), Err(volume::Error::BlockMapUnterminated));
+    }
+
+    #[test]
+    fn volume_block_map_length() {
+        let mut data = firmware_volume();
+        data[0x3D] = 0x08;
+        fix_volume_checksum(&mut data);
+        assert_eq!(
+            validate_volume(&data),
+            Err(volume::Error::BlockMapLength { blocks: 0x800, length: 0x1000 })
+        );
+    }
+
+    #[test]
+    fn volume_truncated() {
+        let data = firmware_volume();
+        assert_eq!(
+            validate_volume(&data[..0x800]),
+            Err(volume::Error::Truncated { length: 0x1000, available: 0x800 })
+        );
+    }
+
+    #[test]
+    fn update_microcode_fit() {
+        let mut data = microcode_image();
+
+        // Replace an update, keeping the one outside of the file
+        RomMut::new(&mut data).unwrap().update_microcode(&microcode_update(0x906EA, 5)).unwrap();
+        assert_eq!(
+            microcode_revisions(&data),
+            vec![(0x60, 0x906EA, 5), (0xA0, 0x806EC, 2), (0x2000, 0xA0655, 3)]
+        );
+        {
+            let bios = Bios::new(&data[0x1000..]).unwrap();
+            let fit = bios.fit().unwrap().unwrap();
+            assert_eq!(fit.entries().len(), 6);
+            assert!(fit.checksum_valid());
+            assert_eq!(fit.items()[3].entry.kind(), fit::EntryKind::StartupAcm);
+            assert_eq!(fit.items()[4].entry.kind(), fit::EntryKind::Unused);
+        }
+
+        // Add an update, reclaiming the unused entry
+        RomMut::new(&mut data).unwrap().update_microcode(&microcode_update(0x306C3, 7)).unwrap();
+        assert_eq!(
+            microcode_revisions(&data),
+            vec![(0x60, 0x906EA, 5), (0xA0, 0x806EC, 2), (0xE0, 0x306C3, 7), (0x2000, 0xA0655, 3)]
+        );
+        let bios = Bios::new(&data[0x1000..]).unwrap();
+        let fit = bios.fit().unwrap().unwrap();
+        assert_eq!(fit.entries().len(), 6);
+        assert!(fit.checksum_valid());
+        assert_eq!(fit.items()[4].entry.kind(), fit::EntryKind::StartupAcm);
+    }
+
+    #[test]
+    fn update_microcode_outside_file() {
+        let mut data = microcode_image();
+        assert!(RomMut::new(&mut data).unwrap().update_microcode(&microcode_update(0xA0655, 4)).is_err());
+    }
+
+    #[test]
+    fn update_microcode_full() {
+        let mut data = microcode_image();
+        let mut rom = RomMut::new(&mut data).unwrap();
+        rom.update_microcode(&microcode_update(0xB0671, 1)).unwrap();
+        rom.update_microcode(&microcode_update(0xC0671, 1)).unwrap();
+
+        // The file holds four updates
+        let full = data.clone();
+        assert_eq!(
+            RomMut::new(&mut data).unwrap().update_microcode(&microcode_update(0xD0671, 1)),
+            Err(String::from("Microcode file too small: 0x140 > 0x100"))
+        );
+        assert_eq!(data, full);
+    }
+
+    /// Sums of the FFS header, with the file checksum and state as zero, and of the file data
+    /// with the file checksum
+    fn ffs_checksums(data: &[u8], offset: usize) -> (u8, u8) {
+        let header = &data[offset..offset + 0x18];
+        let size = header[0x14] as usize | (header[0x15] as usize) << 8 | (header[0x16] as usize) << 16;
+        let sum = |bytes: &[u8]| bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        (
+            sum(header).wrapping_sub(header[0x11]).wrapping_sub(header[0x17]),
+            sum(&data[offset + 0x18..offset + size]).wrapping_add(header[0x11]),
+        )
+    }
+
+    #[test]
+    fn update_microcode_checksum() {
+        // Microcode file with header and data checksums
+        let mut data = microcode_image();
+        let file = 0x1048;
+        data[file + 0x13] = file::Attributes::ATTRIB_CHECKSUM.bits();
+        let (header, contents) = ffs_checksums(&data, file);
+        data[file + 0x10] = header.wrapping_neg();
+        data[file + 0x11] = contents.wrapping_neg();
+        assert_eq!(ffs_checksums(&data, file), (0, 0));
+
+        let header = data[file..file + 0x11].to_vec();
+        RomMut::new(&mut data).unwrap().update_microcode(&microcode_update(0x906EA, 5)).unwrap();
+        assert_eq!(microcode_revisions(&data)[0], (0x60, 0x906EA, 5));
+        assert_eq!(ffs_checksums(&data, file), (0, 0));
+        assert_eq!(data[file..file + 0x11], header[..]);
+
+        RomMut::new(&mut data).unwrap().update_microcode(&microcode_update(0xB0671, 1)).unwrap();
+        assert_eq!(ffs_checksums(&data, file), (0, 0));
+    }
+
+    #[test]
+    fn flash_density() {
+        let mut data = image(0);
+        let rom = Rom::new(&data).unwrap();
+        assert_eq!(rom.flash_components(), 1);
+        assert_eq!(rom.flash_density(), Ok(0x80000));
+        assert_eq!(
+            rom.flash_density_warning(),
+            Ok(Some(String::from("Flash density 512 K does not match image length 16 K")))
+        );
+
+        // Two components, both 512 KiB
+        data[0x15] = 0x01;
+        let rom = Rom::new(&data).unwrap();
+        assert_eq!(rom.flash_components(), 2);
+        assert_eq!(rom.flash_density(), Ok(0x100000));
+    }
+
+    #[test]
+    fn descriptor_locked() {
+        let mut data = image(0);
+        write_masters(&mut data, &[0x0A0B_0000, 0x0C0D_0000, 0x0808_0118]);
+        let rom = Rom::new(&data).unwrap();
+        assert_eq!(rom.master_access().unwrap().len(), 3);
+        assert_eq!(rom.master_access_issues(), Ok(vec![]));
+        assert_eq!(rom.descriptor_locked(), Ok(true));
+
+        write_masters(&mut data, &[0xFFFF_0000, 0x0C0D_0000, 0x0808_0118]);
+        let rom = Rom::new(&data).unwrap();
+        assert_eq!(rom.master_access_issues(), Ok(vec![
+            flash::AccessIssue::DescriptorWritable(flash::MasterKind::Host),
+            flash::AccessIssue::ManagementEngineWritable(flash::MasterKind::Host),
+        ]));
+        assert_eq!(rom.descriptor_locked(), Ok(false));
+    }
+
+    fn read_masters(data: &[u8], count: usize) -> Vec<u32> {
+        data[0x80..0x80 + count * 4].chunks(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+    }
+
+    #[test]
+    fn extract_region() {
+        let mut data = image(0);
+        data[0x1000] = 0x12;
+        data[0x3FFF] = 0x34;
+        let rom = Rom::new(&data).unwrap();
+        let bios = rom.extract_region(RegionKind::Bios).unwrap().unwrap();
+        assert_eq!(bios.len(), 0x3000);
+        assert_eq!((bios[0], bios[0x2FFF]), (0x12, 0x34));
+        assert_eq!(rom.extract_region(RegionKind::Descriptor).unwrap().unwrap(), data[..0x1000].to_vec());
+        assert_eq!(rom.extract_region(RegionKind::Ethernet), Ok(None));
+
+        // BIOS region past the end of the image
+        write_u32(&mut data, 0x44, 0x0007_0001);
+        assert_eq!(
+            Rom::new(&data).unwrap().extract_region(RegionKind::Bios),
+            Err(String::from("Bios region invalid: 32767 >= 16384"))
+        );
+    }
+
+    #[test]
+    fn inject_region() {
+        let mut data = image(0);
+        let descriptor = data[..0x1000].to_vec();
+
+        RomMut::new(&mut data).unwrap().inject_region(RegionKind::Bios, &[0xAB; 0x3000], false).unwrap();
+        assert!(data[0x1000..].iter().all(|&b| b == 0xAB));
+        assert_eq!(data[..0x1000], descriptor[..]);
+
+        RomMut::new(&mut data).unwrap().inject_region(RegionKind::Bios, &[0xCD; 0x100], true).unwrap();
+        assert!(data[0x1000..0x1100].iter().all(|&b| b == 0xCD));
+        assert!(data[0x1100..].iter().all(|&b| b == 0xFF));
+        assert_eq!(data[..0x1000], descriptor[..]);
+    }
+
+    #[test]
+    fn inject_region_invalid() {
+        let mut data = image(0);
+        let original = data.clone();
+        let mut rom = RomMut::new(&mut data).unwrap();
+
+        assert_eq!(
+            rom.inject_region(RegionKind::Bios, &[0xAB; 0x3001], true),
+            Err(String::from("BIOS region too small: 12289 > 12288"))
+        );
+        assert_eq!(
+            rom.inject_region(RegionKind::Bios, &[0xAB; 0x100], false),
+            Err(String::from("BIOS region size mismatch: 256 < 12288"))
+        );
+        assert_eq!(
+            rom.inject_region(RegionKind::Ethernet, &[0xAB; 0x100], true),
+            Err(String::from("GbE region not present"))
+        );
+        assert_eq!(data, original);
+    }
+
+    /// Image with the ME region at 0x1000 - 0x2FFF and the BIOS region at 0x3000 - 0x3FFF
+    fn relayout_image() -> Vec<u8> {
+        let mut data = image(0);
+        write_u32(&mut data, 0x44, 0x0003_0003);
+        write_u32(&mut data, 0x48, 0x0002_0001);
+        data[0x1000..0x2000].iter_mut().for_each(|b| *b = 0x11);
+        data[0x2000..0x3000].iter_mut().for_each(|b| *b = 0x12);
+        data[0x3000..0x4000].iter_mut().for_each(|b| *b = 0x22);
+        data
+    }
+
+    fn region(kind: RegionKind, base: usize, limit: usize) -> FlashRegion {
+        FlashRegion { kind, base, limit }
+    }
+
+    #[test]
+    fn relayout_shrink() {
+        let mut data = relayout_image();
+        let original = data.clone();
+
+        // Shrink the ME and grow the BIOS down into the freed space
+        RomMut::new(&mut data).unwrap().relayout(&[
+            region(RegionKind::ManagementEngine, 0x1000, 0x1FFF),
+            region(RegionKind::Bios, 0x2000, 0x3FFF),
+        ]).unwrap();
+        assert_eq!(data[0x44..0x4C], [0x02, 0x00, 0x03, 0x00, 0x01, 0x00, 0x01, 0x00]);
+        assert!(data[0x1000..0x2000].iter().all(|&b| b == 0x11));
+        assert!(data[0x2000..0x3000].iter().all(|&b| b == 0xFF));
+        assert!(data[0x3000..0x4000].iter().all(|&b| b == 0x22));
+        assert_eq!(Rom::new(&data).unwrap().validate(), Ok(vec![]));
+
+        // Restore the original layout, the truncated part of the ME stays erased
+        RomMut::new(&mut data).unwrap().relayout(&[
+            region(RegionKind::ManagementEngine, 0x1000, 0x2FFF),
+            region(RegionKind::Bios, 0x3000, 0x3FFF),
+        ]).unwrap();
+        assert_eq!(data[..0x1000], original[..0x1000]);
+        assert!(data[0x1000..0x2000].iter().all(|&b| b == 0x11));
+        assert!(data[0x2000..0x3000].iter().all(|&b| b == 0xFF));
+        assert_eq!(data[0x3000..], original[0x3000..]);
+    }
+
+    #[test]
+    fn relayout_move() {
+        let mut data = relayout_image();
+        RomMut::new(&mut data).unwrap().relayout(&[
+            region(RegionKind::ManagementEngine, 0x2000, 0x2FFF),
+        ]).unwrap();
+        assert_eq!(data[0x48..0x4C], [0x02, 0x00, 0x02, 0x00]);
+        assert!(data[0x1000..0x2000].iter().all(|&b| b == 0xFF));
+        assert!(data[0x2000..0x3000].iter().all(|&b| b == 0x11));
+        assert!(data[0x3000..0x4000].iter().all(|&b| b == 0x22));
+    }
+
+    #[test]
+    fn relayout_invalid() {
+        let mut data = relayout_image();
+        let original = data.clone();
+        let mut rom = RomMut::new(&mut data).unwrap();
+
+        assert_eq!(
+            rom.relayout(&[region(RegionKind::ManagementEngine, 0x1000, 0x3FFF)]),
+            Err(String::from("Flash layout invalid: BIOS region overlaps Intel ME region"))
+        );
+        assert_eq!(
+            rom.relayout(&[region(RegionKind::ManagementEngine, 0x1000, 0x17FF)]),
+            Err(String::from("Flash layout invalid: Intel ME region not aligned to 4 KiB"))
+        );
+        assert_eq!(
+            rom.relayout(&[region(RegionKind::Bios, 0x3000, 0x4FFF)]),
+            Err(String::from("Flash layout invalid: BIOS region extends past the end of the image"))
+        );
+        assert_eq!(
+            rom.relayout(&[region(RegionKind::EmbeddedController, 0x2000, 0x2FFF)]),
+            Err(String::from("EC region not supported by V1 descriptor"))
+        );
+        assert_eq!(data, original);
+    }
+
+    #[test]
+    fn vscc_table() {
+        let mut data = image(0);
+        assert_eq!(Rom::new(&data).unwrap().vscc_table().unwrap().len(), 0);
+
+        // Two entries at 0xDF0, the length is in dwords
+        write_u32(&mut data, 0xEFC, 0x0000_04DF);
+        write_u32(&mut data, 0xDF0, 0x0017_40EF);
+        write_u32(&mut data, 0xDF4, 0x2005_2005);
+        write_u32(&mut data, 0xDF8, 0x0018_20C2);
+        write_u32(&mut data, 0xDFC, 0x2005_2005);
+        let rom = Rom::new(&data).unwrap();
+        let table = rom.vscc_table().unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!((table[0].vendor_id(), table[0].device_id()), (0xEF, 0x4017));
+        assert_eq!((table[1].vendor_id(), table[1].device_id()), (0xC2, 0x2018));
+        assert_eq!(table[1].lower().erase_opcode, 0x20);
+    }
+
+    #[test]
+    fn lock_unlock_v1() {
+        let mut data = image(0);
+        write_masters(&mut data, &[0xFFFF_0000, 0xFFFF_0000, 0xFFFF_0118]);
+
+        RomMut::new(&mut data).unwrap().lock().unwrap();
+        assert_eq!(read_masters(&data, 3), vec![0x0A0B_0000, 0x0C0D_0000, 0x0808_0118]);
+        assert_eq!(Rom::new(&data).unwrap().descriptor_locked(), Ok(true));
+
+        RomMut::new(&mut data).unwrap().unlock().unwrap();
+        assert_eq!(read_masters(&data, 3), vec![0xFFFF_0000, 0xFFFF_0000, 0x0808_0118]);
+        assert_eq!(Rom::new(&data).unwrap().descriptor_locked(), Ok(false));
+    }
+
+    #[test]
+    fn lock_unlock_v2() {
+        // Only the descriptor and BIOS regions are present
+        let mut data = image(6);
+        write_masters(&mut data, &[0xFFFF_FFFF; 5]);
+
+        RomMut::new(&mut data).unwrap().lock().unwrap();
+        assert_eq!(read_masters(&data, 5), vec![0x0020_0300, 0x0000_0100, 0, 0, 0]);
+        assert_eq!(Rom::new(&data).unwrap().descriptor_locked(), Ok(true));
+
+        RomMut::new(&mut data).unwrap().unlock().unwrap();
+        assert_eq!(read_masters(&data, 5), vec![0xFFFF_FF00, 0xFFFF_FF00, 0xFFFF_FF00, 0, 0xFFFF_FF00]);
+    }
+
+    #[test]
+    fn descriptor_version_read_clock() {
+        let data = image(0);
+        assert_eq!(Rom::new(&data).unwrap().descriptor_version(), Ok(flash::Version::V1));
+        let data = image(4);
+        assert_eq!(Rom::new(&data).unwrap().descriptor_version(), Ok(flash::Version::V2));
+        let data = image(6);
+        assert_eq!(Rom::new(&data).unwrap().descriptor_version(), Ok(flash::Version::V2));
+    }
+
+    #[test]
+    fn regions_unknown_version() {
+        let data = image(5);
+        let rom = Rom::new(&data).unwrap();
+        assert!(rom.descriptor_version().is_err());
+        assert_eq!(rom.get_region_base_limit(RegionKind::Bios), Ok(Some((0x1000, 0x3FFF))));
+        assert_eq!(rom.bios().unwrap().unwrap().data().len(), 0x3000);
+        assert_eq!(rom.regions().unwrap().count(), 2);
+    }
+
+    #[test]
+    fn ich_me_disable() {
+        let mut data = image(0);
+        // FPSBA 0x100 with 2 straps, FMSBA 0x200 with 1 strap
+        write_u32(&mut data, 0x18, 0x0210_0000);
+        write_u32(&mut data, 0x1C, 0x0000_0120);
+        write_u32(&mut data, 0x100, 0x0000_0000);
+
+        let mut rom = RomMut::new(&mut data).unwrap();
+        assert_eq!(rom.rom().unwrap().chipset().unwrap().chipset, Chipset::Ich);
+        assert_eq!(rom.rom().unwrap().high_assurance_platform(), Ok(false));
+
+        rom.set_high_assurance_platform(true).unwrap();
+        assert_eq!(rom.rom().unwrap().high_assurance_platform(), Ok(true));
+        assert_eq!(data[0x100..0x104], [0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(data[0x200..0x204], [0x81, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn hap_unknown_version() {
+        // ME region covering the whole BIOS region, which is not a valid ME
+        let mut data = image(5);
+        write_u32(&mut data, 0x48, 0x0003_0001);
+        // FPSBA 0x100 with 1 strap, HAP set
+        write_u32(&mut data, 0x18, 0x0110_0000);
+        write_u32(&mut data, 0x100, HAP);
+
+        let rom = Rom::new(&data).unwrap();
+        assert!(rom.chipset().is_err());
+        assert_eq!(rom.high_assurance_platform(), Ok(true));
+
+        write_u32(&mut data, 0x100, 0);
+        assert_eq!(Rom::new(&data).unwrap().high_assurance_platform(), Ok(false));
+    }
+
+    #[test]
+    fn ec_identity() {
+        let mut data = vec![0xFF; 0x100];
+        let ec = Ec::new(&data).unwrap();
+        assert!(ec.is_empty());
+        assert_eq!(ec.size(), 0);
+        assert_eq!(ec.board(), None);
+
+        let board = b"76EC_BOARD=system76/lemp9\0";
+        let version = b"76EC_VERSION=2021-03-01_1b2bc0f\0";
+        data[..0x80].iter_mut().for_each(|b| *b = 0);
+        data[0x10..0x10 + board.len()].copy_from_slice(board);
+        data[0x40..0x40 + version.len()].copy_from_slice(version);
+        let ec = Ec::new(&data).unwrap();
+        assert!(!ec.is_empty());
+        assert_eq!(ec.size(), 0x80);
+        assert_eq!(ec.board(), Some("system76/lemp9"));
+        assert_eq!(ec.version(), Some("2021-03-01_1b2bc0f"));
+
+        // Unterminated or invalid UTF-8 identifiers are ignored
+        let mut data = vec![0; 0x20];
+        data[..board.len() - 1].copy_from_slice(&board[..board.len() - 1]);
+        assert_eq!(Ec::new(&data[..board.len() - 1]).unwrap().board(), None);
+        data[0x0B] = 0xFF;
+        assert_eq!(Ec::new(&data).unwrap().board(), None);
+    }
+
+    #[test]
+    fn gbe_phy_config() {
+        let mut data = vec![0xFF; gbe::BANK_SIZE * 2];
+        for b in data[..0x80].iter_mut() {
+            *b = 0;
         }
+        // Valid bank, PHY configuration at dword 0x08 with one entry
+        data[0x13 * 2 + 1] = 0x80;
+        data[0x14 * 2] = 0x08;
+        data[0x15 * 2 + 1] = 0x01;
+        data[0x20..0x24].copy_from_slice(&[0x34, 0x12, 0x17, 0x00]);
+
+        let gbe = Gbe::new(&data).unwrap();
+        assert_eq!(gbe.phy_config(), Ok(vec![gbe::PhyConfig { address: 0x17, data: 0x1234 }]));
+
+        // Area past the end of the bank
+        data[0x14 * 2..0x14 * 2 + 2].copy_from_slice(&[0xFF, 0x03]);
+        data[0x15 * 2 + 1] = 0x02;
+        let gbe = Gbe::new(&data).unwrap();
+        assert_eq!(gbe.phy_config(), Err(String::from("GbE PHY configuration invalid: 0xFFC - 0x1004")));
     }
 }
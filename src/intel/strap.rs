@@ -0,0 +1,183 @@
+// SPDX-License-Identifier: MIT
+
+use super::chipset::Chipset;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StrapTable {
+    /// PCH (or ICH) straps, found through FLMAP1
+    Pch,
+    /// Processor (or MCH) straps, found through FLMAP2
+    Processor,
+}
+
+/// Location of a soft strap field
+#[derive(Clone, Copy, Debug)]
+pub struct StrapField {
+    pub name: &'static str,
+    pub table: StrapTable,
+    /// Index of the strap dword in its table
+    pub index: usize,
+    pub shift: u32,
+    pub width: u32,
+}
+
+impl StrapField {
+    pub fn mask(&self) -> u32 {
+        (((1u64 << self.width) - 1) as u32) << self.shift
+    }
+
+    pub fn get(&self, strap: u32) -> u32 {
+        (strap & self.mask()) >> self.shift
+    }
+
+    pub fn set(&self, strap: u32, value: u32) -> u32 {
+        (strap & !self.mask()) | ((value << self.shift) & self.mask())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StrapValue {
+    pub field: &'static StrapField,
+    pub value: u32,
+}
+
+/// ICHSTRP0 bit 0, from ich9gen
+const ICH_ME_DISABLE: StrapField = StrapField {
+    name: "ICH MeDisable",
+    table: StrapTable::Pch,
+    index: 0,
+    shift: 0,
+    width: 1,
+};
+
+/// ICHSTRP0 bit 7, from ich9gen
+const ICH_TCO_MODE: StrapField = StrapField {
+    name: "TCO Mode",
+    table: StrapTable::Pch,
+    index: 0,
+    shift: 7,
+    width: 1,
+};
+
+/// ICHSTRP0 bit 24, from ich9gen
+const ICH_DMI_REQID_DISABLE: StrapField = StrapField {
+    name: "DMI RequesterID Check Disable",
+    table: StrapTable::Pch,
+    index: 0,
+    shift: 24,
+    width: 1,
+};
+
+/// MCHSTRP0 bit 0, from ich9gen
+const MCH_ME_DISABLE: StrapField = StrapField {
+    name: "MCH MeDisable",
+    table: StrapTable::Processor,
+    index: 0,
+    shift: 0,
+    width: 1,
+};
+
+/// MCHSTRP0 bit 7, from ich9gen
+const MCH_ALT_ME_DISABLE: StrapField = StrapField {
+    name: "MCH AltMeDisable",
+    table: StrapTable::Processor,
+    index: 0,
+    shift: 7,
+    width: 1,
+};
+
+/// PCHSTRP10 bit 7 on 5 through 9 series PCHs, from me_cleaner
+const PCH_ALT_ME_DISABLE: StrapField = StrapField {
+    name: "AltMeDisable",
+    table: StrapTable::Pch,
+    index: 10,
+    shift: 7,
+    width: 1,
+};
+
+/// PCHSTRP0 bit 16 on 100 series PCHs and later, from me_cleaner
+const PCH_HAP: StrapField = StrapField {
+    name: "High Assurance Platform",
+    table: StrapTable::Pch,
+    index: 0,
+    shift: 16,
+    width: 1,
+};
+
+static ICH: [StrapField; 5] = [
+    ICH_ME_DISABLE,
+    ICH_TCO_MODE,
+    ICH_DMI_REQID_DISABLE,
+    MCH_ME_DISABLE,
+    MCH_ALT_ME_DISABLE,
+];
+
+static IBEX_PEAK: [StrapField; 1] = [
+    PCH_ALT_ME_DISABLE,
+];
+
+static SUNRISE_POINT: [StrapField; 1] = [
+    PCH_HAP,
+];
+
+/// Soft strap fields with a documented location for a chipset generation
+///
+/// This covers the ME disable straps, and the TCO mode and DMI RequesterID straps of ICH8
+/// through ICH10. Other straps, such as the SPI voltage and top swap size, are not decoded.
+pub fn fields(chipset: Chipset) -> &'static [StrapField] {
+    match chipset {
+        Chipset::Ich => &ICH,
+        Chipset::IbexPeak |
+        Chipset::CougarPoint |
+        Chipset::LynxPoint => &IBEX_PEAK,
+        Chipset::SunrisePoint |
+        Chipset::CannonPoint |
+        Chipset::TigerLake |
+        Chipset::AlderLake => &SUNRISE_POINT,
+    }
+}
+
+static ICH_ME_DISABLE_STRAPS: [StrapField; 3] = [
+    ICH_ME_DISABLE,
+    MCH_ME_DISABLE,
+    MCH_ALT_ME_DISABLE,
+];
+
+/// Straps that disable the ME, which must all be set. ICH8 through ICH10 use the MeDisable
+/// straps of both the ICH and MCH tables, and AltMeDisable in the MCH table, like ich9gen.
+/// Later chipsets use AltMeDisable or HAP.
+pub fn me_disable(chipset: Chipset) -> &'static [StrapField] {
+    match chipset {
+        Chipset::Ich => &ICH_ME_DISABLE_STRAPS,
+        Chipset::IbexPeak |
+        Chipset::CougarPoint |
+        Chipset::LynxPoint => &IBEX_PEAK,
+        Chipset::SunrisePoint |
+        Chipset::CannonPoint |
+        Chipset::TigerLake |
+        Chipset::AlderLake => &SUNRISE_POINT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field() {
+        assert_eq!(PCH_HAP.mask(), 0x0001_0000);
+        assert_eq!(PCH_HAP.get(0x1234_5678), 0);
+        assert_eq!(PCH_HAP.set(0x1234_5678, 1), 0x1235_5678);
+        assert_eq!(PCH_HAP.set(0x1235_5678, 0), 0x1234_5678);
+        // Values wider than the field are truncated
+        assert_eq!(PCH_ALT_ME_DISABLE.set(0, 3), 0x80);
+    }
+
+    #[test]
+    fn chipset_fields() {
+        assert_eq!(fields(Chipset::Ich).len(), 5);
+        assert_eq!(me_disable(Chipset::Ich).len(), 3);
+        assert_eq!(me_disable(Chipset::CougarPoint)[0].index, 10);
+        assert_eq!(me_disable(Chipset::AlderLake)[0].shift, 16);
+    }
+}
@@ -0,0 +1,253 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::{FlashRegion, RegionKind};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FindingKind {
+    /// No descriptor region is defined
+    DescriptorMissing,
+    /// The descriptor region does not start at offset 0
+    DescriptorOffset(usize),
+    /// The region does not start and end on a 4 KiB boundary
+    Unaligned(RegionKind),
+    /// The region extends past the end of the image
+    BeyondImage(RegionKind),
+    /// The region extends past the density declared in the component table
+    BeyondDensity(RegionKind),
+    /// Two regions share flash space
+    Overlap(RegionKind, RegionKind),
+    /// Space outside of all regions that is not erased
+    Gap { base: usize, limit: usize },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub kind: FindingKind,
+}
+
+impl Finding {
+    fn error(kind: FindingKind) -> Self {
+        Finding {
+            severity: Severity::Error,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            FindingKind::DescriptorMissing => {
+                write!(f, "{} region missing", RegionKind::Descriptor)
+            },
+            FindingKind::DescriptorOffset(base) => {
+                write!(f, "{} region at {:#X} instead of 0", RegionKind::Descriptor, base)
+            },
+            FindingKind::Unaligned(kind) => {
+                write!(f, "{} region not aligned to 4 KiB", kind)
+            },
+            FindingKind::BeyondImage(kind) => {
+                write!(f, "{} region extends past the end of the image", kind)
+            },
+            FindingKind::BeyondDensity(kind) => {
+                write!(f, "{} region extends past the flash density", kind)
+            },
+            FindingKind::Overlap(a, b) => {
+                write!(f, "{} region overlaps {} region", a, b)
+            },
+            FindingKind::Gap { base, limit } => {
+                write!(f, "Gap {:#X} - {:#X} is not erased", base, limit)
+            },
+        }
+    }
+}
+
+/// Check region placement against each other, the image length and the flash density
+pub fn layout(regions: &[FlashRegion], len: usize, density: Option<usize>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    match regions.iter().find(|region| region.kind == RegionKind::Descriptor) {
+        Some(descriptor) => if descriptor.base != 0 {
+            findings.push(Finding::error(FindingKind::DescriptorOffset(descriptor.base)));
+        },
+        None => findings.push(Finding::error(FindingKind::DescriptorMissing)),
+    }
+
+    for (i, region) in regions.iter().enumerate() {
+        if region.base & 0xfff != 0 || (region.limit + 1) & 0xfff != 0 {
+            findings.push(Finding::error(FindingKind::Unaligned(region.kind)));
+        }
+
+        if region.base > region.limit || region.limit >= len {
+            findings.push(Finding::error(FindingKind::BeyondImage(region.kind)));
+        }
+
+        if let Some(density) = density {
+            if region.limit >= density {
+                findings.push(Finding::error(FindingKind::BeyondDensity(region.kind)));
+            }
+        }
+
+        for other in regions[i + 1..].iter() {
+            if other.base <= region.limit && region.base <= other.limit {
+                findings.push(Finding::error(FindingKind::Overlap(region.kind, other.kind)));
+            }
+        }
+    }
+
+    findings
+}
+
+/// Check that space outside of all regions is erased
+pub fn gaps(regions: &[FlashRegion], data: &[u8]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    let mut sorted = regions.to_vec();
+    sorted.sort_by_key(|region| region.base);
+
+    let mut check = |base: usize, end: usize| {
+        let end = end.min(data.len());
+        if base < end && data[base..end].iter().any(|&b| b != 0xFF) {
+            findings.push(Finding {
+                severity: Severity::Warning,
+                kind: FindingKind::Gap { base, limit: end - 1 },
+            });
+        }
+    };
+
+    let mut offset = 0;
+    for region in sorted.iter() {
+        check(offset, region.base);
+        offset = offset.max(region.limit + 1);
+    }
+    check(offset, data.len());
+
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use super::*;
+
+    fn region(kind: RegionKind, base: usize, limit: usize) -> FlashRegion {
+        FlashRegion { kind, base, limit }
+    }
+
+    #[test]
+    fn layout_valid() {
+        let regions = [
+            region(RegionKind::Descriptor, 0, 0xFFF),
+            region(RegionKind::Bios, 0x1000, 0x3FFF),
+        ];
+        assert_eq!(layout(&regions, 0x4000, Some(0x4000)), vec![]);
+        assert_eq!(layout(&regions, 0x4000, None), vec![]);
+    }
+
+    #[test]
+    fn layout_descriptor() {
+        let regions = [region(RegionKind::Bios, 0x1000, 0x1FFF)];
+        assert_eq!(
+            layout(&regions, 0x2000, None),
+            vec![Finding::error(FindingKind::DescriptorMissing)]
+        );
+
+        let regions = [region(RegionKind::Descriptor, 0x1000, 0x1FFF)];
+        let findings = layout(&regions, 0x2000, None);
+        assert_eq!(findings, vec![Finding::error(FindingKind::DescriptorOffset(0x1000))]);
+        assert_eq!(findings[0].to_string(), "Flash Descriptor region at 0x1000 instead of 0");
+    }
+
+    #[test]
+    fn layout_errors() {
+        let regions = [
+            region(RegionKind::Descriptor, 0, 0xFFF),
+            region(RegionKind::ManagementEngine, 0x1000, 0x27FF),
+            region(RegionKind::Bios, 0x2000, 0x4FFF),
+        ];
+        assert_eq!(layout(&regions, 0x4000, Some(0x2000)), vec![
+            Finding::error(FindingKind::Unaligned(RegionKind::ManagementEngine)),
+            Finding::error(FindingKind::BeyondDensity(RegionKind::ManagementEngine)),
+            Finding::error(FindingKind::Overlap(RegionKind::ManagementEngine, RegionKind::Bios)),
+            Finding::error(FindingKind::BeyondImage(RegionKind::Bios)),
+            Finding::error(FindingKind::BeyondDensity(RegionKind::Bios)),
+        ]);
+    }
+
+    #[test]
+    fn layout_inverted() {
+        let regions = [
+            region(RegionKind::Descriptor, 0, 0xFFF),
+            region(RegionKind::Bios, 0x2000, 0xFFF),
+        ];
+        assert_eq!(
+            layout(&regions, 0x4000, None),
+            vec![Finding::error(FindingKind::BeyondImage(RegionKind::Bios))]
+        );
+    }
+
+    #[test]
+    fn gaps_erased() {
+        let regions = [
+            region(RegionKind::Descriptor, 0, 0xFFF),
+            region(RegionKind::Bios, 0x2000, 0x2FFF),
+        ];
+        let mut data = vec![0xFF; 0x4000];
+        data[..0x1000].iter_mut().for_each(|b| *b = 0);
+        data[0x2000..0x3000].iter_mut().for_each(|b| *b = 0);
+        assert_eq!(gaps(&regions, &data), vec![]);
+    }
+
+    #[test]
+    fn gaps_not_erased() {
+        // Unsorted regions, with gaps between them and at the end of the image
+        let regions = [
+            region(RegionKind::Bios, 0x2000, 0x2FFF),
+            region(RegionKind::Descriptor, 0, 0xFFF),
+        ];
+        let mut data = vec![0xFF; 0x4000];
+        data[0x1800] = 0;
+        data[0x3FFF] = 0;
+
+        let findings = gaps(&regions, &data);
+        assert_eq!(findings, vec![
+            Finding {
+                severity: Severity::Warning,
+                kind: FindingKind::Gap { base: 0x1000, limit: 0x1FFF },
+            },
+            Finding {
+                severity: Severity::Warning,
+                kind: FindingKind::Gap { base: 0x3000, limit: 0x3FFF },
+            },
+        ]);
+        assert_eq!(findings[0].to_string(), "Gap 0x1000 - 0x1FFF is not erased");
+    }
+
+    #[test]
+    fn gaps_past_image() {
+        // A region past the end of the image must not cause an out of bounds check
+        let regions = [
+            region(RegionKind::Descriptor, 0, 0xFFF),
+            region(RegionKind::Bios, 0x8000, 0x8FFF),
+        ];
+        let mut data = vec![0xFF; 0x2000];
+        data[0x1000] = 0;
+        assert_eq!(gaps(&regions, &data), vec![Finding {
+            severity: Severity::Warning,
+            kind: FindingKind::Gap { base: 0x1000, limit: 0x1FFF },
+        }]);
+    }
+}
@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: MIT
 
 use bitflags::bitflags;
+use core::fmt;
 use plain::Plain;
 use uefi::guid::Guid;
 
@@ -69,3 +70,40 @@ pub struct BlockEntry {
 }
 
 unsafe impl Plain for BlockEntry {}
+
+/// Problems found when validating a firmware volume
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// Header length does not fit the header and block map, or the volume
+    HeaderLength(u16),
+    /// Header does not sum to zero, with the sum that was found
+    Checksum(u16),
+    /// Block map has no terminating entry within the header
+    BlockMapUnterminated,
+    /// Volume length does not equal the total size of the block map
+    BlockMapLength { blocks: u64, length: u64 },
+    /// Volume extends past the end of the containing data
+    Truncated { length: u64, available: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::HeaderLength(header_length) => write!(f, "header length {:#X} invalid", header_length),
+            Error::Checksum(sum) => write!(f, "header checksum invalid: sum {:#06X}", sum),
+            Error::BlockMapUnterminated => write!(f, "block map not terminated"),
+            Error::BlockMapLength { blocks, length } => write!(
+                f,
+                "block map size {:#X} does not match length {:#X}",
+                blocks,
+                length
+            ),
+            Error::Truncated { length, available } => write!(
+                f,
+                "length {:#X} beyond available {:#X}",
+                length,
+                available
+            ),
+        }
+    }
+}
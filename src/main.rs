@@ -1,7 +1,8 @@
 // SPDX-License-Identifier: MIT
 
 use romulan::intel::{Rom, BiosFile, BiosSection, BiosSections, BiosVolume, BiosVolumes};
-use romulan::intel::{section, volume};
+use romulan::intel::{mfs, section, volume};
+use romulan::intel::family::Sku;
 use std::{env, fs, io, mem, process, thread};
 use std::io::{Read, Write};
 use std::process::{Command, Stdio};
@@ -111,6 +112,9 @@ fn dump_volume(volume: &BiosVolume, padding: &str) {
     let attributes = header.attributes();
     println!("{}{}: {}, {} K", padding, guid, header_len, len);
     println!("{}  Attrib: {:?}", padding, attributes);
+    if let Err(err) = volume.validate() {
+        println!("{}  Error: {}", padding, err);
+    }
 
     let polarity = attributes.contains(volume::Attributes::ERASE_POLARITY);
     for file in volume.files() {
@@ -118,7 +122,7 @@ fn dump_volume(volume: &BiosVolume, padding: &str) {
     }
 }
 
-fn romulan(path: &str) -> Result<(), String> {
+fn romulan(path: &str, chipset: Option<(u16, u16, u16)>) -> Result<(), String> {
     println!("{}", path);
 
     let mut data = Vec::new();
@@ -130,14 +134,239 @@ fn romulan(path: &str) -> Result<(), String> {
 
     let rom = Rom::new(&data)?;
 
-    if rom.high_assurance_platform()? {
-        println!("  HAP: set");
-    } else {
-        println!("  HAP: not set");
+    match rom.chipset() {
+        Ok(guess) => {
+            println!("  Chipset: {} ({:?} confidence)", guess.chipset, guess.confidence);
+            match rom.straps(guess.chipset) {
+                Ok(values) => for value in values {
+                    println!("    {}: {}", value.field.name, value.value);
+                },
+                Err(err) => {
+                    println!("    Straps: {}", err);
+                }
+            }
+        },
+        Err(err) => {
+            println!("  Chipset: {}", err);
+        }
+    }
+
+    match rom.high_assurance_platform() {
+        Ok(true) => println!("  HAP: set"),
+        Ok(false) => println!("  HAP: not set"),
+        Err(err) => println!("  HAP: {}", err),
+    }
+
+    match rom.descriptor_version().and_then(|version| Ok((version, rom.flash_component()?))) {
+        Ok((version, component)) => {
+            println!("  Flash Component: {:?}", version);
+            for chip in 0..rom.flash_components() {
+                if let Some(density) = component.density(chip, version) {
+                    println!("    Chip {}: {} K", chip, density / 1024);
+                } else {
+                    println!("    Chip {}: Unused", chip);
+                }
+            }
+            println!("    Read Clock: {}", component.read_clock(version));
+            if component.fast_read() {
+                println!("    Fast Read Clock: {}", component.fast_read_clock(version));
+            } else {
+                println!("    Fast Read Clock: Not supported");
+            }
+            println!("    Write/Erase Clock: {}", component.erase_clock(version));
+            println!("    Read ID/Status Clock: {}", component.read_id_clock(version));
+            println!("    Dual Output Fast Read: {}", component.dual_output_fast_read());
+            println!("    Invalid Instructions: {:02X?}", component.invalid_instructions());
+            match rom.flash_density_warning() {
+                Ok(Some(warning)) => println!("    Warning: {}", warning),
+                Ok(None) => (),
+                Err(err) => println!("    Density: {}", err),
+            }
+
+            match rom.vscc_table() {
+                Ok(entries) => for entry in entries {
+                    let lower = entry.lower();
+                    println!(
+                        "    VSCC {:02X}:{:04X}: Erase {:02X} {} B, Write {} B",
+                        entry.vendor_id(),
+                        entry.device_id(),
+                        lower.erase_opcode,
+                        lower.block_erase_size,
+                        lower.write_granularity
+                    );
+                },
+                Err(err) => {
+                    println!("    VSCC: {}", err);
+                }
+            }
+
+            match rom.master_access() {
+                Ok(masters) => {
+                    println!("  Flash Masters:");
+                    for access in masters.iter() {
+                        println!("    {}: Read {:04X} Write {:04X}", access.kind, access.read, access.write);
+                    }
+                    let issues: Vec<_> = masters.iter().flat_map(|access| access.issues()).collect();
+                    if issues.is_empty() {
+                        println!("    Descriptor: locked");
+                    } else {
+                        println!("    Descriptor: unlocked");
+                        for issue in issues {
+                            println!("    Warning: {}", issue);
+                        }
+                    }
+                },
+                Err(err) => {
+                    println!("  Flash Masters: {}", err);
+                }
+            }
+        },
+        Err(err) => {
+            println!("  Flash Component: {}", err);
+        }
+    }
+
+    if let Ok(findings) = rom.validate() {
+        for finding in findings {
+            println!("  {:?}: {}", finding.severity, finding);
+        }
     }
 
     if let Some(bios) = rom.bios()? {
         println!("  BIOS: {} K", bios.data().len()/1024);
+        match bios.fit() {
+            Ok(Some(fit)) => {
+                println!("    FIT: {:#X}", fit.offset());
+                for item in fit.items() {
+                    let kind = item.entry.kind();
+                    let version = item.entry.version;
+                    if let Some(offset) = item.offset {
+                        println!("      {}: {:#X}, version {:#X}", kind, offset, version);
+                    } else {
+                        let address = item.entry.address;
+                        println!("      {}: address {:#X}, version {:#X}", kind, address, version);
+                    }
+                }
+                if ! fit.checksum_valid() {
+                    println!("      Checksum: invalid");
+                }
+            },
+            Ok(None) => (),
+            Err(err) => {
+                println!("    FIT: {}", err);
+            }
+        }
+        match bios.microcode() {
+            Ok(updates) => for (offset, update) in updates {
+                let (year, month, day) = update.date();
+                println!(
+                    "    Microcode {:#X}: {} revision {:#X}, {:04}-{:02}-{:02}, checksum {}",
+                    offset,
+                    update.signature(),
+                    update.revision(),
+                    year,
+                    month,
+                    day,
+                    if update.checksum_valid() && update.extended_checksum_valid() { "valid" } else { "invalid" }
+                );
+                for (signature, flags) in update.processors().iter().skip(1) {
+                    println!("      Extended: {} flags {:#X}", signature, flags);
+                }
+            },
+            Err(err) => {
+                println!("    Microcode: {}", err);
+            }
+        }
+        match bios.startup_acm() {
+            Ok(Some(acm)) => {
+                let header = acm.header();
+                let (year, month, day) = acm.date();
+                let (svn, size) = (header.txt_svn, header.size as usize * 4);
+                println!("    Startup ACM: {:04}-{:02}-{:02}, SVN {}, {} K", year, month, day, svn, size / 1024);
+                if header.debug() {
+                    println!("      Debug signed");
+                }
+                match acm.chipset_ids() {
+                    Ok(ids) => for id in ids {
+                        let (vendor, device, revision) = (id.vendor_id, id.device_id, id.revision_id);
+                        println!("      Chipset: {:04X}:{:04X} revision {:#X}", vendor, device, revision);
+                    },
+                    Err(err) => {
+                        println!("      Chipset: {}", err);
+                    }
+                }
+                match acm.processor_ids() {
+                    Ok(ids) => for id in ids {
+                        let (fms, mask) = (id.fms, id.fms_mask);
+                        println!("      Processor: {:05X} mask {:05X}", fms, mask);
+                    },
+                    Err(err) => {
+                        println!("      Processor: {}", err);
+                    }
+                }
+                match bios.startup_acm_matches_microcode() {
+                    Ok(Some(false)) => {
+                        println!("      Warning: does not support every processor with microcode");
+                    },
+                    Ok(_) => (),
+                    Err(err) => {
+                        println!("      Microcode check: {}", err);
+                    }
+                }
+                if let Some((vendor, device, revision)) = chipset {
+                    match bios.startup_acm_matches_chipset(vendor, device, revision) {
+                        Ok(Some(false)) => {
+                            println!(
+                                "      Warning: does not support chipset {:04X}:{:04X} revision {:#X}",
+                                vendor, device, revision
+                            );
+                        },
+                        Ok(_) => (),
+                        Err(err) => {
+                            println!("      Chipset check: {}", err);
+                        }
+                    }
+                }
+            },
+            Ok(None) => (),
+            Err(err) => {
+                println!("    Startup ACM: {}", err);
+            }
+        }
+        match bios.boot_guard() {
+            Ok(Some(boot_guard)) => {
+                println!("    Boot Guard:");
+                if ! boot_guard.supported() {
+                    println!("      Version {:#X} not supported", boot_guard.version);
+                }
+                if let Some(km) = boot_guard.key_manifest {
+                    println!("      KM: version {:#X}, SVN {}, ID {}", km.km_version, km.km_svn, km.km_id);
+                    print!("        Key Hash: ");
+                    for b in km.key_hash().iter() {
+                        print!("{:02x}", b);
+                    }
+                    println!();
+                }
+                if let Some(bpm) = &boot_guard.boot_policy {
+                    println!("      BPM: version {:#X}, SVN {}, ACM SVN {}", bpm.header.bpm_version, bpm.header.bp_svn, bpm.header.acm_svn);
+                    for segment in bpm.segments {
+                        let (base, size, flags) = (segment.base, segment.size, segment.flags);
+                        println!("        IBB: {:#X} {:#X} flags {:#X}", base, size, flags);
+                    }
+                    match bpm.ibb_hash_valid(bios.data()) {
+                        Ok(valid) => println!("        IBB Hash: {}", if valid { "valid" } else { "invalid" }),
+                        Err(err) => println!("        IBB Hash: {}", err),
+                    }
+                }
+                if let Some(valid) = boot_guard.boot_policy_key_valid() {
+                    println!("      BPM Key: {}", if valid { "valid" } else { "invalid" });
+                }
+            },
+            Ok(None) => (),
+            Err(err) => {
+                println!("    Boot Guard: {}", err);
+            }
+        }
         for volume in bios.volumes() {
             dump_volume(&volume, "    ");
         }
@@ -145,6 +374,39 @@ fn romulan(path: &str) -> Result<(), String> {
         println!("  BIOS: None");
     }
 
+    if let Some(gbe) = rom.gbe()? {
+        println!("  GbE: {} K", gbe.data().len()/1024);
+        match gbe.mac_address() {
+            Ok(mac) => {
+                println!(
+                    "    MAC: {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+                    mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+                );
+                let (major, minor, build) = gbe.version()?;
+                println!("    Version: {}.{}-{}", major, minor, build);
+                println!("    Checksum: {}", if gbe.checksum_valid()? { "valid" } else { "invalid" });
+            },
+            Err(err) => {
+                println!("    Error: {}", err);
+            }
+        }
+    } else {
+        println!("  GbE: None");
+    }
+
+    if let Some(ec) = rom.ec()? {
+        println!("  EC: {} K", ec.data().len()/1024);
+        if ec.is_empty() {
+            println!("    Empty");
+        } else {
+            println!("    Board: {}", ec.board().unwrap_or("Unknown"));
+            println!("    Version: {}", ec.version().unwrap_or("Unknown"));
+            println!("    Size: {} K", ec.size()/1024);
+        }
+    } else {
+        println!("  EC: None");
+    }
+
     if let Some(me) = rom.me()? {
         println!("  ME: {} K", me.data().len()/1024);
         if let Some(version) = me.version() {
@@ -152,6 +414,116 @@ fn romulan(path: &str) -> Result<(), String> {
         } else {
             println!("    Version: Unknown");
         }
+        match me.family() {
+            Ok(family) => {
+                println!("    Family: {}", family);
+                match me.sku_hints() {
+                    Ok(hints) => {
+                        println!("    SKU: {:?}", hints.first().map_or(Sku::Consumer, |hint| hint.sku()));
+                        for hint in hints {
+                            println!("      Hint: {}", hint);
+                        }
+                    },
+                    Err(err) => {
+                        println!("    SKU: {}", err);
+                    }
+                }
+            },
+            Err(err) => {
+                println!("    Family: {}", err);
+            }
+        }
+        if let Ok(Some(manifest)) = me.manifest() {
+            println!("    Manifest: {:?}", manifest.kind());
+            println!("      Date: {}", manifest.date());
+            println!("      SVN: {}", manifest.svn());
+            if let Some(vcn) = manifest.vcn() {
+                println!("      VCN: {}", vcn);
+            }
+            print!("      Key Hash: ");
+            for b in manifest.key_hash().iter() {
+                print!("{:02x}", b);
+            }
+            println!();
+            if manifest.header().debug() {
+                println!("      Debug signed");
+            }
+        }
+        match me.fpt() {
+            Ok(fpt) => {
+                let header = fpt.header();
+                println!(
+                    "    $FPT: {:#X}, version {:#X}, checksum {}",
+                    fpt.offset(),
+                    header.header_version,
+                    if fpt.checksum_valid() { "valid" } else { "invalid" }
+                );
+                for entry in fpt.entries() {
+                    let (offset, length) = (entry.offset, entry.length);
+                    print!("      {}: {:?} {:#X} {} K", entry.name(), entry.kind(), offset, length / 1024);
+                    if ! entry.valid() {
+                        print!(" (invalid)");
+                    } else if entry.disabled() {
+                        print!(" (disabled)");
+                    }
+                    println!();
+                }
+            },
+            Err(err) => {
+                println!("    $FPT: {}", err);
+            }
+        }
+        match me.mfs() {
+            Ok(Some(mfs)) => {
+                println!("    MFS: {} files", mfs.num_files());
+                if ! mfs.bad_chunks().is_empty() {
+                    println!("      Bad chunks: {:?}", mfs.bad_chunks());
+                }
+                for (index, file) in mfs.files() {
+                    match file {
+                        Ok(data) => println!("      {}: {} B", index, data.len()),
+                        Err(err) => println!("      {}: {}", index, err),
+                    }
+                }
+                for (name, index) in [("intel.cfg", mfs::INTEL_CFG), ("fitc.cfg", mfs::FITC_CFG)] {
+                    match mfs.config(index) {
+                        Ok(Some(config)) => {
+                            println!("    {}: {} records", name, config.records().len());
+                            for record in config.records() {
+                                if record.directory() {
+                                    println!("      {}/", record.name());
+                                } else {
+                                    let size = record.size;
+                                    println!("      {}: {} B", record.name(), size);
+                                }
+                            }
+                        },
+                        Ok(None) => (),
+                        Err(err) => {
+                            println!("    {}: {}", name, err);
+                        }
+                    }
+                }
+            },
+            Ok(None) => (),
+            Err(err) => {
+                println!("    MFS: {}", err);
+            }
+        }
+        if let Ok(cpds) = me.cpds() {
+            for cpd in cpds {
+                println!("    $CPD {}:", cpd.partition_name());
+                for module in cpd.modules() {
+                    println!(
+                        "      {}: {:#X} {} K {:?}",
+                        module.name,
+                        module.offset,
+                        module.data.len() / 1024,
+                        module.compression
+                    );
+                }
+            }
+        }
     } else {
         println!("  ME: None");
     }
@@ -160,9 +532,33 @@ fn romulan(path: &str) -> Result<(), String> {
 }
 
 
+/// Parse TXT chipset IDs given as `vendor:device:revision` in hex
+fn parse_chipset(value: &str) -> Result<(u16, u16, u16), String> {
+    let ids = value.split(':').map(|id| u16::from_str_radix(id, 16)).collect::<Result<Vec<u16>, _>>().map_err(|err| {
+        format!("invalid chipset {}: {}", value, err)
+    })?;
+    match ids[..] {
+        [vendor, device, revision] => Ok((vendor, device, revision)),
+        _ => Err(format!("invalid chipset {}: expected vendor:device:revision", value)),
+    }
+}
+
 fn main() {
+    // Check the startup ACM against a chipset given with --chipset=vendor:device:revision
+    let mut chipset = None;
     for arg in env::args().skip(1) {
-        if let Err(err) = romulan(&arg) {
+        if let Some(value) = arg.strip_prefix("--chipset=") {
+            match parse_chipset(value) {
+                Ok(ids) => chipset = Some(ids),
+                Err(err) => {
+                    eprintln!("romulan: {}", err);
+                    process::exit(1);
+                }
+            }
+            continue;
+        }
+
+        if let Err(err) = romulan(&arg, chipset) {
             eprintln!("romulan: {}: {}", arg, err);
             process::exit(1);
         }